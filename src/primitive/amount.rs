@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use super::{Decode, Decoder, Encode};
+use super::{PrimitiveError, Result};
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+/// Value of coins in base units.
+/// Arithmetic is checked so that no value exceeds `Amount::MAX_MONEY`.
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Self = Self(0);
+    /// Number of base units in a coin
+    pub const COIN: u64 = 100_000_000;
+    /// Number of digits after the decimal point of a coin
+    pub const DECIMALS: usize = 8;
+    /// Upper bound of any amount, which is also the maximum money supply
+    pub const MAX_MONEY: Self = Self(21_000_000 * Self::COIN);
+
+    pub const fn new(base_units: u64) -> Self {
+        Self(base_units)
+    }
+
+    pub fn as_base_units(&self) -> u64 {
+        self.0
+    }
+
+    /// Whether the amount does not exceed the maximum money supply
+    pub fn is_valid(&self) -> bool {
+        *self <= Self::MAX_MONEY
+    }
+
+    /// Add the amounts, failing if the sum exceeds the maximum money supply
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        Some(Self(self.0.checked_add(other.0)?)).filter(Self::is_valid)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    /// Halve the amount `times` times, rounding down
+    pub fn halved(self, times: u128) -> Self {
+        u32::try_from(times)
+            .ok()
+            .and_then(|times| self.0.checked_shr(times))
+            .map_or(Self::ZERO, Self)
+    }
+
+    /// Sum up the amounts, failing if the sum exceeds the maximum money supply
+    pub fn checked_sum(amounts: impl IntoIterator<Item = Self>) -> Option<Self> {
+        amounts
+            .into_iter()
+            .try_fold(Self::ZERO, |sum, amount| sum.checked_add(amount))
+    }
+}
+
+/// Formats the amount in coins, e.g. `1.5` or `0.00000001`
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let whole = self.0 / Self::COIN;
+        let fraction = self.0 % Self::COIN;
+        if fraction == 0 {
+            return write!(f, "{}", whole);
+        }
+        let fraction = format!("{:0width$}", fraction, width = Self::DECIMALS);
+        write!(f, "{}.{}", whole, fraction.trim_end_matches('0'))
+    }
+}
+
+/// Parses an amount in coins with up to `Amount::DECIMALS` digits after the decimal point
+impl FromStr for Amount {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason| PrimitiveError::InvalidAmount {
+            amount: s.to_owned(),
+            reason,
+        };
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+        let is_digits = |digits: &str| digits.bytes().all(|byte| byte.is_ascii_digit());
+        if whole.is_empty()
+            || !is_digits(whole)
+            || !is_digits(fraction)
+            || (s.contains('.') && fraction.is_empty())
+        {
+            return Err(invalid("not a decimal number"));
+        }
+        if fraction.len() > Self::DECIMALS {
+            return Err(invalid("too many decimal places"));
+        }
+        let fraction = format!("{:0<width$}", fraction, width = Self::DECIMALS);
+        let whole: u64 = whole
+            .parse()
+            .map_err(|_| invalid("exceeds the maximum money supply"))?;
+        let fraction: u64 = fraction
+            .parse()
+            .map_err(|_| invalid("not a decimal number"))?;
+        whole
+            .checked_mul(Self::COIN)
+            .and_then(|whole| whole.checked_add(fraction))
+            .map(Self)
+            .filter(Self::is_valid)
+            .ok_or_else(|| invalid("exceeds the maximum money supply"))
+    }
+}
+
+impl Encode for Amount {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.0.encode_to(buf);
+    }
+}
+
+impl Decode for Amount {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        Ok(Self(u64::decode_from(decoder)?))
+    }
+}
+
+#[test]
+fn test_amount_arithmetic_and_format() {
+    assert_eq!(
+        Some(Amount::MAX_MONEY),
+        Amount::MAX_MONEY.checked_add(Amount::ZERO)
+    );
+    assert_eq!(None, Amount::MAX_MONEY.checked_add(Amount::new(1)));
+    assert_eq!(None, Amount::new(u64::MAX).checked_add(Amount::new(1)));
+    assert_eq!(None, Amount::new(1).checked_sub(Amount::new(2)));
+    assert_eq!(
+        Some(Amount::new(6)),
+        Amount::checked_sum([1, 2, 3].map(Amount::new))
+    );
+    assert_eq!(Amount::new(12), Amount::new(50).halved(2));
+    assert_eq!(Amount::ZERO, Amount::new(50).halved(200));
+
+    assert_eq!("0", Amount::ZERO.to_string());
+    assert_eq!("1.5", Amount::new(150_000_000).to_string());
+    assert_eq!("0.00000001", Amount::new(1).to_string());
+    assert_eq!("21000000", Amount::MAX_MONEY.to_string());
+    for s in ["0", "1.5", "0.00000001", "21000000", "12.3456789"] {
+        assert_eq!(s, s.parse::<Amount>().unwrap().to_string());
+    }
+    assert_eq!(Amount::new(100_000_000), "1.00".parse().unwrap());
+    for s in [
+        "",
+        ".5",
+        "1.",
+        "-1",
+        "+1",
+        "1.000000001",
+        "21000000.00000001",
+        "1e3",
+        "1 ",
+    ] {
+        assert!(s.parse::<Amount>().is_err(), "{:?} was parsed", s);
+    }
+}
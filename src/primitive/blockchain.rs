@@ -1,22 +1,34 @@
+use rsa::BigUint;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use super::hash::{Hash, Hashable};
 use super::Address;
-use super::Hash;
-use super::PublicKey;
-use super::Result;
-use crate::err;
+use super::Amount;
+use super::Mempool;
+use super::OrphanPool;
+#[cfg(test)]
+use super::SignatureScheme;
+use super::{encode_len, Decode, Decoder, Encode, ENCODING_VERSION};
+use super::{first_invalid_signature, PrivateKey, PublicKey, SignatureCache, SignedMessage};
+use super::{merkle_root, MerkleProof};
+use super::{PrimitiveError, Result};
+use super::{Target, VerifyPow};
+use super::{UtxoDiff, UtxoEntry, UtxoSet, UtxoView};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// Output of transaction
 pub struct TxOut {
     /// Address of the account of the receiver
     receiver_address: Address,
     /// Value to be transferred
-    amount: usize,
+    amount: Amount,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 /// Reference to point a transaction output from a transaction input
 pub struct TxOutPtr {
     /// Hash of the transaction holding the output
@@ -25,43 +37,53 @@ pub struct TxOutPtr {
     index: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// Input of the transaction
 pub struct TxIn {
     /// Signature to prove that the transaction creator owns `src_output`
-    signature: Hash,
+    #[serde(with = "super::serde_hex::bytes")]
+    signature: Vec<u8>,
     /// Public key used to verify `signature`
     public_key: PublicKey,
     /// Transaction output where the input came from.
     source_output: TxOutPtr,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// Transaction representing a transfer of value
 pub struct Transaction {
     /// Values that will be consumed after the transaction
     inputs: Vec<TxIn>,
     /// Values that will be generated after the transaction
     outputs: Vec<TxOut>,
+    /// Height of the block for a base transaction, which keeps the hashes of base transactions unique.
+    /// Always zero for the other transactions.
+    coinbase_height: u128,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// Addtional data attached to blocks
 pub struct BlockDesc {
     /// The block preceding this block in the blockchain
     parent_hash: Hash,
+    /// Root of the Merkle tree of the hashes of the transactions in the block
+    merkle_root: Hash,
+    /// Compact representation of the target which the hash of this block must not exceed
+    bits: u32,
+    /// Time when the block was created, in seconds since the UNIX epoch
+    timestamp: u64,
     /// Random number used to adjust the hash of the block
     nonce: u128,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// Transactions contained in a block
 pub struct BlockBody {
     /// List of transactions contained in a block
     transactions: Vec<Transaction>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// Block consists of some transactions
 pub struct Block {
     /// Addtional data attached to the block
@@ -70,201 +92,1014 @@ pub struct Block {
     body: BlockBody,
 }
 
+#[derive(Debug, Default)]
+/// Changes made to the main chain by pushing a block
+pub struct ChainUpdate {
+    /// Blocks removed from the main chain, from the old tip to the fork point
+    pub disconnected: Vec<Hash>,
+    /// Blocks added to the main chain, from the fork point to the new tip
+    pub connected: Vec<Hash>,
+    /// Transactions of the disconnected blocks which are not included in the connected blocks,
+    /// in the order they appeared in the chain.
+    /// Base transactions are not included since they are only valid in their own block.
+    pub orphaned: Vec<Transaction>,
+}
+
+impl ChainUpdate {
+    /// Combine with the update made after this update
+    fn merge(&mut self, next: ChainUpdate) {
+        for hash in next.disconnected {
+            if self.connected.last() == Some(&hash) {
+                self.connected.pop();
+            } else {
+                self.disconnected.push(hash);
+            }
+        }
+        self.connected.extend(next.connected);
+        self.orphaned.extend(next.orphaned);
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Parameters of the consensus rules which every node of a network must agree on
+pub struct ConsensusParams {
+    /// Easiest target of Proof-of-Work, which is also the target of the first blocks
+    pub pow_limit: Target,
+    /// Time expected between blocks, in seconds
+    pub block_interval: u64,
+    /// Number of blocks between retargets of Proof-of-Work
+    pub retarget_interval: u128,
+    /// How far a block timestamp can be ahead of the local clock, in seconds
+    pub max_future_block_time: u64,
+    /// Amount of new coins the base transaction of a block can claim before the first halving
+    pub initial_subsidy: Amount,
+    /// Number of blocks between halvings of the subsidy
+    pub halving_interval: u128,
+    /// Number of blocks which must be built on top of a base transaction before its outputs can be spent
+    pub coinbase_maturity: u128,
+}
+
+impl ConsensusParams {
+    /// Amount of new coins the base transaction of the block at `height` can claim.
+    /// This halves every `halving_interval` blocks until it reaches zero.
+    pub fn subsidy(&self, height: u128) -> Amount {
+        self.initial_subsidy
+            .halved(height / self.halving_interval.max(1))
+    }
+}
+
 #[derive(Debug)]
 /// Blockchain is a tree consisting of blocks
 pub struct Blockchain {
-    /// Transactions in the blockchain
-    transactions: HashMap<Hash, Transaction>,
+    /// Hash of the block containing each transaction in the main chain
+    transactions: HashMap<Hash, Hash>,
     /// Blocks in the blockchain
     blocks: HashMap<Hash, BlockDesc>,
+    /// Transactions of the blocks in the blockchain
+    bodies: HashMap<Hash, BlockBody>,
     /// Height of the blocks in the tree
     block_heights: HashMap<Hash, u128>,
-    /// Height of the highest block in the tree
-    max_height: u128,
-    /// Hash of the highest block in the tree
-    max_height_block_hash: Hash,
-    /// Difficulty of Proof-of-Work (number of preceding bits which must be zero)
-    pow_difficulty: usize,
+    /// Total work of the blocks from the root to each block
+    chain_works: HashMap<Hash, BigUint>,
+    /// Hash of the tip of the main chain, which is the block with the most total work
+    best_block_hash: Hash,
+    /// Unspent transaction outputs at the tip of the main chain
+    utxos: UtxoSet,
+    /// Changes made to the unspent transaction outputs by each block
+    utxo_diffs: HashMap<Hash, UtxoDiff>,
+    /// Consensus rules the blocks must follow
+    params: ConsensusParams,
+    /// Blocks received before their parent
+    orphans: OrphanPool,
+    /// Transactions waiting to be included in a block on top of the main chain
+    mempool: Mempool,
+    /// Signatures verified when transactions entered the mempool or blocks were verified
+    signatures: SignatureCache,
 }
 
-trait VerifyPow {
-    fn pow_verified(&self, difficulty: usize) -> bool;
-}
+impl TxOut {
+    pub fn new(receiver_address: Address, amount: Amount) -> Self {
+        Self {
+            receiver_address,
+            amount,
+        }
+    }
+
+    pub fn receiver_address(&self) -> &Address {
+        &self.receiver_address
+    }
 
-impl VerifyPow for Hash {
-    fn pow_verified(&self, difficulty: usize) -> bool {
-        assert!(difficulty <= 8);
-        self[0] & !(0b1111_1111_u8 >> difficulty) == 0
+    pub fn amount(&self) -> Amount {
+        self.amount
     }
 }
 
-// TODO: Implment auto derive for hash()
-impl TxOut {
-    fn hash(&self) -> Hash {
-        let mut hasher = Sha256::new();
-        hasher.update(self.receiver_address.as_hash());
-        hasher.update(self.amount.to_le_bytes());
-        hasher.finalize()
+impl TxOutPtr {
+    pub fn new(transaction_hash: Hash, index: usize) -> Self {
+        Self {
+            transaction_hash,
+            index,
+        }
+    }
+
+    pub fn transaction_hash(&self) -> &Hash {
+        &self.transaction_hash
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
     }
 }
 
 impl TxIn {
-    fn hash(&self) -> Hash {
-        let mut hasher = Sha256::new();
-        hasher.update(self.signature);
-        // TODO: forward the errors to the caller
-        hasher.update(
-            self.public_key
-                .hash()
-                .expect("failed to calculate public key hash"),
-        );
-        hasher.update(self.source_output.transaction_hash);
-        hasher.update(self.source_output.index.to_le_bytes());
-        hasher.finalize()
+    /// Create an unsigned input, which is signed later by `Transaction::sign_inputs`
+    pub fn new(public_key: PublicKey, source_output: TxOutPtr) -> Self {
+        Self {
+            signature: Vec::new(),
+            public_key,
+            source_output,
+        }
+    }
+
+    pub fn source_output(&self) -> &TxOutPtr {
+        &self.source_output
+    }
+
+    /// Consume the output this input of the transaction of `spender` came from, failing unless it is unspent
+    fn find_source(&self, spender: &Hash, utxos: &mut UtxoView) -> Result<UtxoEntry> {
+        utxos.spend(spender, &self.source_output)
+    }
+
+    /// Whether the public key of the input belongs to the receiver of `source`, the output it came from
+    fn is_owner_of(&self, source: &TxOut) -> bool {
+        Address::from_public_key(&self.public_key)
+            .is_ok_and(|address| address == source.receiver_address)
     }
 
-    fn find_source<'local>(
+    /// Signature of the input over `sighash`, which is verified together with the others in a block
+    fn signed_message(&self, sighash: Hash) -> SignedMessage<'_> {
+        SignedMessage {
+            public_key: &self.public_key,
+            message: sighash,
+            signature: &self.signature,
+        }
+    }
+
+    /// Whether the input is signed by the owner of `source`, the output it came from
+    pub(crate) fn is_signed_by_owner(
         &self,
-        local_transactions: &HashMap<Hash, &'local Transaction>,
-        chain_transactions: &'local HashMap<Hash, Transaction>,
-    ) -> Result<&'local TxOut> {
-        local_transactions
-            .get(&self.source_output.transaction_hash)
-            .copied()
-            .or_else(|| chain_transactions.get(&self.source_output.transaction_hash))
-            .ok_or_else(|| err!("transaction output referred in transaction input does not found"))
-            .map(|transaction| &transaction.outputs[self.source_output.index])
+        source: &TxOut,
+        sighash: &Hash,
+        signatures: &SignatureCache,
+    ) -> bool {
+        self.is_owner_of(source) && signatures.verify(&self.signed_message(*sighash))
     }
 }
 
 impl Transaction {
-    fn hash(&self) -> Hash {
-        let mut hasher = Sha256::new();
+    pub fn new(inputs: Vec<TxIn>, outputs: Vec<TxOut>) -> Self {
+        Self {
+            inputs,
+            outputs,
+            coinbase_height: 0,
+        }
+    }
+
+    /// Create the base transaction of the block at `height`, which claims the subsidy and the fees
+    pub fn coinbase(height: u128, output: TxOut) -> Self {
+        Self {
+            inputs: Vec::new(),
+            outputs: vec![output],
+            coinbase_height: height,
+        }
+    }
+
+    pub fn coinbase_height(&self) -> u128 {
+        self.coinbase_height
+    }
+
+    pub fn inputs(&self) -> &[TxIn] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[TxOut] {
+        &self.outputs
+    }
+
+    /// Hash signed by the inputs.
+    /// This commits to every input source and output, but not to the signatures themselves.
+    pub fn sighash(&self) -> Hash {
+        let mut buf = vec![ENCODING_VERSION];
+        encode_len(self.inputs.len(), &mut buf);
         for input in &self.inputs {
-            hasher.update(input.hash());
+            input.source_output.encode_to(&mut buf);
         }
-        for output in &self.outputs {
-            hasher.update(output.hash());
+        self.outputs.encode_to(&mut buf);
+        self.coinbase_height.encode_to(&mut buf);
+        Hash::digest(buf)
+    }
+
+    /// Sign each input by the private key of the same index
+    pub fn sign_inputs(&mut self, private_keys: &[PrivateKey]) -> Result<()> {
+        if private_keys.len() != self.inputs.len() {
+            return Err(PrimitiveError::KeyCountMismatch {
+                keys: private_keys.len(),
+                inputs: self.inputs.len(),
+            });
         }
-        hasher.finalize()
+        let sighash = self.sighash();
+        for (input, private_key) in self.inputs.iter_mut().zip(private_keys) {
+            input.public_key = private_key.to_public_key();
+            input.signature = private_key.sign(&sighash)?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDesc {
+    /// Hasher fed with the encoded header except for the nonce, which is encoded last.
+    /// Miners reuse this to try many nonces.
+    pub(crate) fn hasher_without_nonce(&self) -> Sha256 {
+        let encoded = self.encode();
+        Sha256::new().chain_update(&encoded[..encoded.len() - std::mem::size_of::<u128>()])
+    }
+
+    pub fn parent_hash(&self) -> Hash {
+        self.parent_hash
+    }
+
+    pub fn merkle_root(&self) -> Hash {
+        self.merkle_root
+    }
+}
+
+impl BlockBody {
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// Root of the Merkle tree of the transactions
+    pub fn merkle_root(&self) -> Hash {
+        merkle_root(&self.transaction_hashes())
+    }
+
+    /// Proof that the transaction at `index` is included in the Merkle root
+    pub fn merkle_proof(&self, index: usize) -> Option<MerkleProof> {
+        MerkleProof::new(&self.transaction_hashes(), index)
+    }
+
+    fn transaction_hashes(&self) -> Vec<Hash> {
+        self.transactions
+            .iter()
+            .map(|transaction| transaction.hash())
+            .collect()
     }
 }
 
 impl Block {
-    fn new(transactions: Vec<Transaction>, parent_hash: Hash) -> Self {
+    /// Timestamp of the genesis block (2022-06-01T00:00:00Z)
+    pub const GENESIS_TIMESTAMP: u64 = 1_654_041_600;
+
+    /// Create a block timestamped with the current time
+    pub fn new(transactions: Vec<Transaction>, parent_hash: Hash, target: Target) -> Self {
+        let body = BlockBody { transactions };
         Self {
-            body: BlockBody { transactions },
             desc: BlockDesc {
                 parent_hash,
+                merkle_root: body.merkle_root(),
+                bits: target.to_compact(),
+                timestamp: now(),
                 nonce: 0,
             },
+            body,
         }
     }
 
-    fn from_part(body: BlockBody, desc: BlockDesc) -> Self {
-        Self { body, desc }
+    /// Create the first block of a blockchain, which has no parent and only a base transaction
+    /// paying `coinbase`
+    pub fn genesis(coinbase: TxOut, target: Target) -> Self {
+        let mut genesis = Self::new(
+            vec![Transaction::coinbase(0, coinbase)],
+            Hash::default(),
+            target,
+        );
+        genesis.set_timestamp(Self::GENESIS_TIMESTAMP);
+        genesis
     }
 
-    fn hash(&self) -> Hash {
-        let mut hasher = Sha256::new();
-        hasher.update(self.desc.parent_hash);
-        for transaction in &self.body.transactions {
-            hasher.update(transaction.hash());
-        }
-        hasher.update(self.desc.nonce.to_le_bytes());
-        hasher.finalize()
+    pub(crate) fn hasher_without_nonce(&self) -> Sha256 {
+        self.desc.hasher_without_nonce()
+    }
+
+    pub fn desc(&self) -> &BlockDesc {
+        &self.desc
+    }
+
+    pub fn body(&self) -> &BlockBody {
+        &self.body
+    }
+
+    /// Proof that the transaction at `index` is included in this block
+    pub fn merkle_proof(&self, index: usize) -> Option<MerkleProof> {
+        self.body.merkle_proof(index)
+    }
+
+    pub fn parent_hash(&self) -> Hash {
+        self.desc.parent_hash
+    }
+
+    /// Target which the hash of this block must not exceed
+    pub fn target(&self) -> Result<Target> {
+        Target::from_compact(self.desc.bits)
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.desc.timestamp
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: u64) {
+        self.desc.timestamp = timestamp;
+    }
+
+    pub fn nonce(&self) -> u128 {
+        self.desc.nonce
+    }
+
+    pub fn set_nonce(&mut self, nonce: u128) {
+        self.desc.nonce = nonce;
+    }
+}
+
+impl Encode for TxOut {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.receiver_address.encode_to(buf);
+        self.amount.encode_to(buf);
+    }
+}
+
+impl Decode for TxOut {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        Ok(Self {
+            receiver_address: Address::decode_from(decoder)?,
+            amount: Amount::decode_from(decoder)?,
+        })
     }
+}
+
+/// Formats the pointer as `<transaction hash>:<index>`
+impl fmt::Display for TxOutPtr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:x}:{}", self.transaction_hash, self.index)
+    }
+}
+
+impl Encode for TxOutPtr {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.transaction_hash.encode_to(buf);
+        self.index.encode_to(buf);
+    }
+}
+
+impl Decode for TxOutPtr {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        Ok(Self {
+            transaction_hash: Hash::decode_from(decoder)?,
+            index: usize::decode_from(decoder)?,
+        })
+    }
+}
+
+impl Encode for TxIn {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.signature.encode_to(buf);
+        self.public_key.encode_to(buf);
+        self.source_output.encode_to(buf);
+    }
+}
+
+impl Decode for TxIn {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        Ok(Self {
+            signature: Vec::decode_from(decoder)?,
+            public_key: PublicKey::decode_from(decoder)?,
+            source_output: TxOutPtr::decode_from(decoder)?,
+        })
+    }
+}
+
+impl Encode for Transaction {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.inputs.encode_to(buf);
+        self.outputs.encode_to(buf);
+        self.coinbase_height.encode_to(buf);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        Ok(Self {
+            inputs: Vec::decode_from(decoder)?,
+            outputs: Vec::decode_from(decoder)?,
+            coinbase_height: u128::decode_from(decoder)?,
+        })
+    }
+}
 
-    fn base_transaction(&self) -> &Transaction {
-        &self.body.transactions[0]
+impl Encode for BlockDesc {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.parent_hash.encode_to(buf);
+        self.merkle_root.encode_to(buf);
+        self.bits.encode_to(buf);
+        self.timestamp.encode_to(buf);
+        // The nonce must be the last so that miners can reuse the hasher fed with the other fields
+        self.nonce.encode_to(buf);
     }
 }
 
+impl Decode for BlockDesc {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        Ok(Self {
+            parent_hash: Hash::decode_from(decoder)?,
+            merkle_root: Hash::decode_from(decoder)?,
+            bits: u32::decode_from(decoder)?,
+            timestamp: u64::decode_from(decoder)?,
+            nonce: u128::decode_from(decoder)?,
+        })
+    }
+}
+
+impl Encode for BlockBody {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.transactions.encode_to(buf);
+    }
+}
+
+impl Decode for BlockBody {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        Ok(Self {
+            transactions: Vec::decode_from(decoder)?,
+        })
+    }
+}
+
+impl Encode for Block {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.desc.encode_to(buf);
+        self.body.encode_to(buf);
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        Ok(Self {
+            desc: BlockDesc::decode_from(decoder)?,
+            body: BlockBody::decode_from(decoder)?,
+        })
+    }
+}
+
+impl Hashable for TxOut {}
+
+impl Hashable for TxIn {}
+
+impl Hashable for Transaction {}
+
+/// Hash of the header, which also commits to the nonce
+impl Hashable for BlockDesc {}
+
+/// Blocks are identified by the hash of their header, which commits to the transactions by the Merkle root
+impl Hashable for Block {
+    fn hash(&self) -> Hash {
+        self.desc.hash()
+    }
+}
+
+/// Current time in seconds since the UNIX epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
 impl Blockchain {
-    const TX_PER_BLOCK: usize = 16;
+    /// Maximum number of transactions in a block, excluding the base transaction
+    pub const TX_PER_BLOCK: usize = 16;
+    /// Number of preceding blocks whose median timestamp a new block must exceed
+    pub const MEDIAN_TIME_SPAN: usize = 11;
+
+    /// Create a blockchain whose root is the genesis block paying `genesis`.
+    /// Nodes share the same genesis hash only if they agree on both `genesis` and `params`.
+    pub fn new(genesis: TxOut, params: ConsensusParams) -> Self {
+        let genesis = Block::genesis(genesis, params.pow_limit);
+        let hash = genesis.hash();
+        let empty = UtxoSet::new();
+        let mut utxos = UtxoView::new(&empty);
+        for (i, transaction) in genesis.body.transactions.iter().enumerate() {
+            utxos
+                .create(transaction, 0, i == 0)
+                .expect("genesis block has duplicated outputs");
+        }
+        let genesis_diff = utxos.into_diff();
+        let mut utxos = UtxoSet::new();
+        utxos.apply(&genesis_diff);
+        let transactions = genesis
+            .body
+            .transactions
+            .iter()
+            .map(|transaction| (transaction.hash(), hash))
+            .collect();
+        Self {
+            transactions,
+            blocks: HashMap::from([(hash, genesis.desc)]),
+            bodies: HashMap::from([(hash, genesis.body)]),
+            block_heights: HashMap::from([(hash, 0)]),
+            chain_works: HashMap::from([(hash, params.pow_limit.work())]),
+            best_block_hash: hash,
+            utxos,
+            utxo_diffs: HashMap::from([(hash, genesis_diff)]),
+            params,
+            orphans: OrphanPool::default(),
+            mempool: Mempool::default(),
+            signatures: SignatureCache::default(),
+        }
+    }
+
+    pub fn current_hash(&self) -> Hash {
+        self.best_block_hash
+    }
+
+    /// Height of the tip of the main chain
+    pub fn height(&self) -> u128 {
+        self.block_heights[&self.best_block_hash]
+    }
+
+    /// Look up a transaction in the main chain
+    pub fn transaction(&self, hash: &Hash) -> Option<&Transaction> {
+        let block_hash = self.transactions.get(hash)?;
+        self.bodies[block_hash]
+            .transactions
+            .iter()
+            .find(|transaction| &transaction.hash() == hash)
+    }
+
+    pub fn orphans(&self) -> &OrphanPool {
+        &self.orphans
+    }
 
-    fn current_hash(&self) -> Hash {
-        self.max_height_block_hash
+    pub fn orphans_mut(&mut self) -> &mut OrphanPool {
+        &mut self.orphans
     }
 
-    fn push(&mut self, block: Block) -> Result<BlockBody> {
-        self.verify(&block)?;
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    pub fn mempool_mut(&mut self) -> &mut Mempool {
+        &mut self.mempool
+    }
+
+    /// Validate the transaction against the tip of the main chain and keep it in the mempool
+    pub fn add_transaction(&mut self, transaction: Transaction) -> Result<Hash> {
+        self.mempool.insert(
+            transaction,
+            &self.utxos,
+            self.height() + 1,
+            self.params.coinbase_maturity,
+            &self.signatures,
+        )
+    }
+
+    /// Block on top of the main chain which includes the pending transactions paying the highest fees.
+    /// The base transaction pays the subsidy and the fees to `coinbase_address`, and the block is ready to be mined.
+    pub fn block_template(&self, coinbase_address: Address) -> Result<Block> {
+        let parent_hash = self.current_hash();
+        let height = self.height() + 1;
+        let entries = self.mempool.select(Self::TX_PER_BLOCK);
+        let reward = entries
+            .iter()
+            .try_fold(self.params.subsidy(height), |sum, entry| {
+                sum.checked_add(entry.fee())
+            })
+            .ok_or(PrimitiveError::RewardOverflow {
+                parent: parent_hash,
+            })?;
+        let mut transactions = vec![Transaction::coinbase(
+            height,
+            TxOut::new(coinbase_address, reward),
+        )];
+        transactions.extend(entries.into_iter().map(|entry| entry.transaction().clone()));
+
+        let mut block = Block::new(transactions, parent_hash, self.next_target(&parent_hash)?);
+        let earliest = self.median_time_past(&parent_hash)? + 1;
+        if block.timestamp() < earliest {
+            block.set_timestamp(earliest);
+        }
+        Ok(block)
+    }
+
+    /// Parents of the orphan blocks, which should be requested from peers
+    pub fn missing_parents(&self) -> Vec<Hash> {
+        self.orphans.missing_parents()
+    }
+
+    /// Verify the block and add that to the tree.
+    /// If the block makes a fork have more work than the main chain, the main chain is switched to that fork.
+    /// A block whose parent is unknown is kept in the orphan pool, and connected once the parent is pushed.
+    pub fn push(&mut self, block: Block) -> Result<ChainUpdate> {
         let hash = block.hash();
+        if self.blocks.contains_key(&hash) || self.orphans.contains(&hash) {
+            return Err(PrimitiveError::DuplicateBlock { block: hash });
+        }
+        if !self.blocks.contains_key(&block.desc.parent_hash) {
+            // Check PoW before keeping the block so that peers cannot fill the pool for free
+            let target = block.target()?;
+            if target > self.params.pow_limit || !hash.pow_verified(&target) {
+                return Err(PrimitiveError::InvalidPow { block: hash });
+            }
+            self.orphans.insert(block);
+            return Ok(ChainUpdate::default());
+        }
+
+        let mut update = self.connect(block)?;
+        let mut parents = vec![hash];
+        while let Some(parent_hash) = parents.pop() {
+            for child in self.orphans.take_children(&parent_hash) {
+                let child_hash = child.hash();
+                if let Ok(child_update) = self.connect(child) {
+                    update.merge(child_update);
+                    parents.push(child_hash);
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        update.orphaned.retain(|transaction| {
+            let transaction_hash = transaction.hash();
+            !self.transactions.contains_key(&transaction_hash) && seen.insert(transaction_hash)
+        });
+        self.update_mempool(&update);
+        Ok(update)
+    }
+
+    /// Keep the mempool consistent with the main chain after it is updated
+    fn update_mempool(&mut self, update: &ChainUpdate) {
+        if update.disconnected.is_empty() {
+            for hash in &update.connected {
+                self.mempool
+                    .remove_for_block(&self.bodies[hash].transactions);
+            }
+            return;
+        }
+        // The tip moved to another fork, so every pending transaction is validated again on the new tip,
+        // after the transactions which were confirmed only in the old main chain
+        let pending = self.mempool.drain();
+        let height = self.height() + 1;
+        for transaction in update.orphaned.iter().cloned().chain(pending) {
+            // Transactions conflicting with the new main chain are dropped
+            let _ = self.mempool.insert(
+                transaction,
+                &self.utxos,
+                height,
+                self.params.coinbase_maturity,
+                &self.signatures,
+            );
+        }
+    }
+
+    /// Verify the block whose parent is known and add that to the tree
+    fn connect(&mut self, block: Block) -> Result<ChainUpdate> {
+        let hash = block.hash();
+        let parent_hash = block.desc.parent_hash;
+        let unknown_parent = || PrimitiveError::UnknownParent {
+            block: hash,
+            parent: parent_hash,
+        };
         let height = self
             .block_heights
-            .get(&block.desc.parent_hash)
-            .ok_or_else(|| err!("hash not found in blocks_height"))?
-            .checked_add(1)
-            .ok_or_else(|| err!("block height overflowed"))?;
+            .get(&parent_hash)
+            .ok_or_else(unknown_parent)?
+            + 1;
+        let chain_work = self
+            .chain_works
+            .get(&parent_hash)
+            .ok_or_else(unknown_parent)?
+            + block.target()?.work();
+        let becomes_best = chain_work > self.chain_works[&self.best_block_hash];
+
+        let diff = self.verify_with(&block, self.utxo_view_at(&parent_hash)?)?;
+        self.utxo_diffs.insert(hash, diff);
         self.block_heights.insert(hash, height);
+        self.chain_works.insert(hash, chain_work);
         self.blocks.insert(hash, block.desc);
-        for transaction in &block.body.transactions {
-            self.transactions
-                .insert(transaction.hash(), transaction.clone());
+        self.bodies.insert(hash, block.body);
+        if !becomes_best {
+            return Ok(ChainUpdate::default());
+        }
+
+        let (disconnected, connected) = self.fork_path(&self.best_block_hash, &hash)?;
+        self.best_block_hash = hash;
+        // Only the main chain materializes its unspent outputs, which move along the fork path
+        for block_hash in &disconnected {
+            self.utxos.revert(&self.utxo_diffs[block_hash]);
+        }
+        for block_hash in &connected {
+            self.utxos.apply(&self.utxo_diffs[block_hash]);
+        }
+        for block_hash in &disconnected {
+            for transaction in &self.bodies[block_hash].transactions {
+                self.transactions.remove(&transaction.hash());
+            }
+        }
+        for block_hash in &connected {
+            for transaction in &self.bodies[block_hash].transactions {
+                self.transactions.insert(transaction.hash(), *block_hash);
+            }
         }
-        if height > self.max_height {
-            self.max_height = height;
-            self.max_height_block_hash = hash;
+        let orphaned = disconnected
+            .iter()
+            .rev()
+            .flat_map(|block_hash| self.bodies[block_hash].transactions.iter().skip(1))
+            .filter(|transaction| !self.transactions.contains_key(&transaction.hash()))
+            .cloned()
+            .collect();
+        Ok(ChainUpdate {
+            disconnected,
+            connected,
+            orphaned,
+        })
+    }
+
+    /// Path between two blocks through their fork point.
+    /// Returns the blocks from `from` to the fork point, and from the fork point to `to`, excluding the fork point.
+    fn fork_path(&self, from: &Hash, to: &Hash) -> Result<(Vec<Hash>, Vec<Hash>)> {
+        let height_of = |hash: &Hash| {
+            self.block_heights
+                .get(hash)
+                .copied()
+                .ok_or(PrimitiveError::UnknownBlock { block: *hash })
+        };
+
+        let mut from = *from;
+        let mut to = *to;
+        let mut from_path = Vec::new();
+        let mut to_path = Vec::new();
+        while from != to {
+            if height_of(&from)? >= height_of(&to)? {
+                from_path.push(from);
+                from = self.block_desc(&from)?.parent_hash;
+            } else {
+                to_path.push(to);
+                to = self.block_desc(&to)?.parent_hash;
+            }
         }
-        Ok(block.body)
+        to_path.reverse();
+        Ok((from_path, to_path))
     }
 
-    fn verify(&self, block: &Block) -> Result<()> {
-        if !block.hash().pow_verified(self.pow_difficulty) {
-            return Err(err!("Received block does not meets difficulty of PoW"));
+    /// Unspent transaction outputs right after the block of `hash`.
+    /// This overlays the changes along the fork path on the outputs at the tip of the main chain,
+    /// so that a side chain is verified without copying the whole set.
+    fn utxo_view_at(&self, hash: &Hash) -> Result<UtxoView<'_>> {
+        let diff_of = |hash: &Hash| {
+            self.utxo_diffs
+                .get(hash)
+                .ok_or(PrimitiveError::UnknownBlock { block: *hash })
+        };
+
+        let (disconnected, connected) = self.fork_path(&self.best_block_hash, hash)?;
+        let mut utxos = UtxoView::new(&self.utxos);
+        for hash in &disconnected {
+            utxos.revert(diff_of(hash)?);
+        }
+        for hash in &connected {
+            utxos.apply(diff_of(hash)?);
         }
-        let transactions_in_block: HashMap<Hash, &Transaction> = block
+        Ok(utxos)
+    }
+
+    /// Description of the block of `hash`
+    fn block_desc(&self, hash: &Hash) -> Result<&BlockDesc> {
+        self.blocks
+            .get(hash)
+            .ok_or(PrimitiveError::UnknownBlock { block: *hash })
+    }
+
+    /// Target which a block appended to the block of `parent_hash` must declare.
+    /// Every `retarget_interval` blocks, the target is scaled so that blocks are mined every
+    /// `block_interval` seconds on average; otherwise the target of the parent is inherited.
+    pub fn next_target(&self, parent_hash: &Hash) -> Result<Target> {
+        let parent = self.block_desc(parent_hash)?;
+        let parent_height =
+            *self
+                .block_heights
+                .get(parent_hash)
+                .ok_or(PrimitiveError::UnknownBlock {
+                    block: *parent_hash,
+                })?;
+        let parent_target = Target::from_compact(parent.bits)?;
+        let retarget_interval = self.params.retarget_interval.max(1);
+        if (parent_height + 1) % retarget_interval != 0 {
+            return Ok(parent_target);
+        }
+
+        let first_height = parent_height.saturating_sub(retarget_interval);
+        let mut first = parent;
+        for _ in first_height..parent_height {
+            first = self.block_desc(&first.parent_hash)?;
+        }
+        let actual_timespan = parent.timestamp.saturating_sub(first.timestamp);
+        let expected_timespan = self
+            .params
+            .block_interval
+            .saturating_mul((parent_height - first_height) as u64);
+        Ok(parent_target.retarget(actual_timespan, expected_timespan, &self.params.pow_limit))
+    }
+
+    /// Median timestamp of the last `MEDIAN_TIME_SPAN` blocks up to the block of `hash`
+    fn median_time_past(&self, hash: &Hash) -> Result<u64> {
+        let mut timestamps = Vec::with_capacity(Self::MEDIAN_TIME_SPAN);
+        let mut desc = self.block_desc(hash)?;
+        loop {
+            timestamps.push(desc.timestamp);
+            match self.blocks.get(&desc.parent_hash) {
+                Some(parent) if timestamps.len() < Self::MEDIAN_TIME_SPAN => desc = parent,
+                _ => break,
+            }
+        }
+        timestamps.sort_unstable();
+        Ok(timestamps[timestamps.len() / 2])
+    }
+
+    /// Verify that the block can be appended to its parent
+    pub fn verify(&self, block: &Block) -> Result<()> {
+        let parent_hash = &block.desc.parent_hash;
+        if !self.blocks.contains_key(parent_hash) {
+            return Err(PrimitiveError::UnknownParent {
+                block: block.hash(),
+                parent: *parent_hash,
+            });
+        }
+        self.verify_with(block, self.utxo_view_at(parent_hash)?)?;
+        Ok(())
+    }
+
+    /// Verify the block against the unspent outputs at its parent, returning the changes made by the block
+    fn verify_with(&self, block: &Block, mut utxos: UtxoView) -> Result<UtxoDiff> {
+        let hash = block.hash();
+        let parent_hash = block.desc.parent_hash;
+        let expected = self.next_target(&parent_hash)?.to_compact();
+        if block.desc.bits != expected {
+            return Err(PrimitiveError::UnexpectedTarget {
+                block: hash,
+                bits: block.desc.bits,
+                expected,
+            });
+        }
+        let median_time_past = self.median_time_past(&parent_hash)?;
+        if block.desc.timestamp <= median_time_past {
+            return Err(PrimitiveError::TimestampTooOld {
+                block: hash,
+                median_time_past,
+            });
+        }
+        if block.desc.timestamp > now().saturating_add(self.params.max_future_block_time) {
+            return Err(PrimitiveError::TimestampInFuture {
+                block: hash,
+                timestamp: block.desc.timestamp,
+            });
+        }
+        if !hash.pow_verified(&block.target()?) {
+            return Err(PrimitiveError::InvalidPow { block: hash });
+        }
+        if block.body.transactions.len() > Self::TX_PER_BLOCK + 1 {
+            return Err(PrimitiveError::TooManyTransactions {
+                block: hash,
+                count: block.body.transactions.len(),
+            });
+        }
+        if block.desc.merkle_root != block.body.merkle_root() {
+            return Err(PrimitiveError::InvalidMerkleRoot { block: hash });
+        }
+        let height = self
+            .block_heights
+            .get(&parent_hash)
+            .ok_or(PrimitiveError::UnknownParent {
+                block: hash,
+                parent: parent_hash,
+            })?
+            + 1;
+        let invalid_coinbase = |reason| PrimitiveError::InvalidCoinbase {
+            block: hash,
+            reason,
+        };
+        let base_transaction = block
             .body
             .transactions
-            .iter()
-            .map(|transaction| (transaction.hash(), transaction))
-            .collect();
+            .first()
+            .ok_or_else(|| invalid_coinbase("block does not contain a base transaction"))?;
+        if !base_transaction.inputs.is_empty() || base_transaction.outputs.len() != 1 {
+            return Err(invalid_coinbase(
+                "number of inputs and outputs of base transaction is incorrect",
+            ));
+        }
+        if base_transaction.coinbase_height != height {
+            return Err(invalid_coinbase(
+                "base transaction does not commit to the height of the block",
+            ));
+        }
 
-        let mut block_input_amount = 0;
-        let mut block_output_amount = 0;
+        let mut fees = Amount::ZERO;
+        let mut signed = Vec::new();
+        // Transaction and input index of each signature in `signed`
+        let mut signers = Vec::new();
+        let mut transaction_hashes = HashSet::new();
 
         for (i, transaction) in block.body.transactions.iter().enumerate() {
-            let transaction_input_amount: usize =
-                transaction.inputs.iter().try_fold(0, |sum, input| {
-                    let input_source =
-                        input.find_source(&transactions_in_block, &self.transactions)?;
-
-                    // Ensure that hash of public key matches with the receiver address of input_source
-                    if &input.public_key.hash()? != input_source.receiver_address.as_hash() {
-                        return Err(err!(
-                            "public key of input does not match with address of output"
-                        ));
-                    }
-
-                    input.public_key.verify(&input.hash(), &input.signature)?;
-                    Ok(sum + input_source.amount)
-                })?;
-            let transaction_output_amount: usize =
-                transaction.outputs.iter().map(|output| output.amount).sum();
+            if i != 0 && transaction.coinbase_height != 0 {
+                return Err(invalid_coinbase(
+                    "transaction other than the base transaction commits to a height",
+                ));
+            }
+            // Only the base transaction can create outputs without spending any
+            if i != 0 && transaction.inputs.is_empty() {
+                return Err(invalid_coinbase(
+                    "transaction other than the base transaction has no inputs",
+                ));
+            }
+            let transaction_hash = transaction.hash();
+            if !transaction_hashes.insert(transaction_hash) {
+                return Err(PrimitiveError::DuplicateTransaction {
+                    transaction: transaction_hash,
+                });
+            }
+            let overflow = || PrimitiveError::AmountOverflow {
+                transaction: transaction_hash,
+            };
+            let sighash = transaction.sighash();
+            let mut transaction_input_amount = Amount::ZERO;
+            for (index, input) in transaction.inputs.iter().enumerate() {
+                let input_source = input.find_source(&transaction_hash, &mut utxos)?;
+                if !input_source.is_spendable_at(height, self.params.coinbase_maturity) {
+                    return Err(PrimitiveError::ImmatureCoinbase {
+                        transaction: transaction_hash,
+                        output: input.source_output.clone(),
+                    });
+                }
+                if !input.is_owner_of(input_source.output()) {
+                    return Err(PrimitiveError::BadSignature {
+                        transaction: transaction_hash,
+                        input: index,
+                    });
+                }
+                // Inputs already verified in the mempool or in another block are not verified again
+                let signed_message = input.signed_message(sighash);
+                if !self.signatures.contains(&signed_message) {
+                    signed.push(signed_message);
+                    signers.push((transaction_hash, index));
+                }
+                transaction_input_amount = transaction_input_amount
+                    .checked_add(input_source.output().amount)
+                    .ok_or_else(overflow)?;
+            }
+            let transaction_output_amount =
+                Amount::checked_sum(transaction.outputs.iter().map(|output| output.amount))
+                    .ok_or_else(overflow)?;
 
             // Transaction excepting for the base transaction cannot generate new amount
-            if i != 0 && transaction_output_amount > transaction_input_amount {
-                return Err(err!(
-                    "output amount of transaction exceeded input amount of transaction"
-                ));
+            if i != 0 {
+                fees = transaction_input_amount
+                    .checked_sub(transaction_output_amount)
+                    .ok_or(PrimitiveError::Unbalanced {
+                        transaction: transaction_hash,
+                        input: transaction_input_amount,
+                        output: transaction_output_amount,
+                    })?
+                    .checked_add(fees)
+                    .ok_or(PrimitiveError::RewardOverflow {
+                        parent: parent_hash,
+                    })?;
             }
-            block_input_amount += transaction_input_amount;
-            block_output_amount += transaction_output_amount;
+            utxos.create(transaction, height, i == 0)?;
         }
-        if block_input_amount != block_output_amount {
-            return Err(err!(
-                "input and output amount of transactions are not balanced"
-            ));
+        let reward = self.params.subsidy(height).checked_add(fees).ok_or(
+            PrimitiveError::RewardOverflow {
+                parent: parent_hash,
+            },
+        )?;
+        if base_transaction.outputs[0].amount > reward {
+            return Err(PrimitiveError::ExcessiveReward {
+                block: hash,
+                claimed: base_transaction.outputs[0].amount,
+                allowed: reward,
+            });
         }
-
-        if !block.base_transaction().inputs.is_empty()
-            || block.base_transaction().outputs.len() != 1
-        {
-            return Err(err!(
-                "number of inputs and outputs of base transaction is incorrect"
-            ));
+        // Signatures dominate the time to verify a block, so they are verified last and in parallel
+        if let Some(i) = first_invalid_signature(&signed) {
+            let (transaction, input) = signers[i];
+            return Err(PrimitiveError::BadSignature { transaction, input });
         }
-        Ok(())
+        self.signatures.insert(&signed);
+        Ok(utxos.into_diff())
     }
 }
 
@@ -278,7 +1113,7 @@ pub enum Action {
 
 #[test]
 fn test_hash_pow_verify() {
-    let hash_difficulty3 = Hash::from_slice(&[
+    let hash_difficulty3 = Hash::from([
         0b0001_1010_u8,
         1,
         2,
@@ -312,9 +1147,720 @@ fn test_hash_pow_verify() {
         30,
         31,
     ]);
-    assert!(hash_difficulty3.pow_verified(0));
-    assert!(hash_difficulty3.pow_verified(1));
-    assert!(hash_difficulty3.pow_verified(2));
-    assert!(hash_difficulty3.pow_verified(3));
-    assert!(!hash_difficulty3.pow_verified(4));
+    assert!(hash_difficulty3.pow_verified(&Target::from_leading_zero_bits(0)));
+    assert!(hash_difficulty3.pow_verified(&Target::from_leading_zero_bits(1)));
+    assert!(hash_difficulty3.pow_verified(&Target::from_leading_zero_bits(2)));
+    assert!(hash_difficulty3.pow_verified(&Target::from_leading_zero_bits(3)));
+    assert!(!hash_difficulty3.pow_verified(&Target::from_leading_zero_bits(4)));
+    assert!(!hash_difficulty3.pow_verified(&Target::from_leading_zero_bits(256)));
+}
+
+#[cfg(test)]
+fn test_params() -> ConsensusParams {
+    ConsensusParams {
+        pow_limit: Target::MAX,
+        block_interval: 60,
+        retarget_interval: 4,
+        max_future_block_time: 2 * 60 * 60,
+        initial_subsidy: Amount::new(50),
+        halving_interval: 1000,
+        coinbase_maturity: 0,
+    }
+}
+
+#[cfg(test)]
+fn mined(block: Block) -> Block {
+    use super::{CancelToken, Miner};
+    Miner::new(1)
+        .mine(block, &CancelToken::new())
+        .expect("failed to mine a block")
+}
+
+#[cfg(test)]
+/// Address nobody holds the key of, receiving the outputs of test blocks
+fn null_address() -> Address {
+    Address::new(SignatureScheme::Ed25519, Hash::default())
+}
+
+#[cfg(test)]
+/// Mined block on top of `parent_hash` whose base transaction pays nothing, followed by `transactions`
+fn block_with(
+    parent_hash: Hash,
+    height: u128,
+    timestamp: u64,
+    target: Target,
+    mut transactions: Vec<Transaction>,
+) -> Block {
+    let base_transaction = Transaction::coinbase(height, TxOut::new(null_address(), Amount::ZERO));
+    transactions.insert(0, base_transaction);
+    let mut block = Block::new(transactions, parent_hash, target);
+    block.set_timestamp(timestamp);
+    mined(block)
+}
+
+#[cfg(test)]
+/// Mined block on top of `parent_hash` with only a base transaction paying nothing
+fn empty_block(parent_hash: Hash, height: u128, timestamp: u64) -> Block {
+    block_with(parent_hash, height, timestamp, Target::MAX, Vec::new())
+}
+
+#[test]
+fn test_genesis_and_push() {
+    let params = test_params();
+    let coinbase = |amount| TxOut::new(null_address(), Amount::new(amount));
+    let mut blockchain = Blockchain::new(coinbase(0), params.clone());
+    let genesis_hash = blockchain.current_hash();
+    assert_eq!(
+        genesis_hash,
+        Blockchain::new(coinbase(0), params.clone()).current_hash()
+    );
+    // Blocks whose base transaction differs from the one `empty_block` builds
+    let with_base = |parent_hash, height: u128, base_transaction| {
+        let mut block = Block::new(vec![base_transaction], parent_hash, Target::MAX);
+        block.set_timestamp(Block::GENESIS_TIMESTAMP + height as u64);
+        mined(block)
+    };
+
+    // The base transaction commits to the height, so paying the same output as the genesis block is fine
+    let block = empty_block(genesis_hash, 1, Block::GENESIS_TIMESTAMP + 1);
+    let block_hash = block.hash();
+    blockchain.push(block).expect("failed to push a block");
+    assert_eq!(block_hash, blockchain.current_hash());
+    assert_eq!(2, blockchain.utxos.len());
+
+    let without_height = Transaction::new(Vec::new(), vec![coinbase(0)]);
+    assert!(matches!(
+        blockchain.push(with_base(block_hash, 2, without_height)),
+        Err(PrimitiveError::InvalidCoinbase { .. })
+    ));
+    assert!(matches!(
+        blockchain.push(with_base(
+            block_hash,
+            2,
+            Transaction::coinbase(1, coinbase(0))
+        )),
+        Err(PrimitiveError::InvalidCoinbase { .. })
+    ));
+
+    // Without fees, the base transaction can claim up to the subsidy
+    assert!(matches!(
+        blockchain.push(with_base(
+            block_hash,
+            2,
+            Transaction::coinbase(2, coinbase(51))
+        )),
+        Err(PrimitiveError::ExcessiveReward { .. })
+    ));
+    blockchain
+        .push(with_base(
+            block_hash,
+            2,
+            Transaction::coinbase(2, coinbase(50)),
+        ))
+        .expect("failed to push a block claiming the subsidy");
+    assert_eq!(Amount::new(50), params.subsidy(params.halving_interval - 1));
+    assert_eq!(Amount::new(25), params.subsidy(params.halving_interval));
+    assert_eq!(Amount::ZERO, params.subsidy(params.halving_interval * 64));
+}
+
+#[test]
+fn test_signed_transaction_and_double_spend() {
+    // RSA keys are still accepted for compatibility
+    let mut rng = rand::thread_rng();
+    let private_key = PrivateKey::Rsa(
+        rsa::RsaPrivateKey::new(&mut rng, 512).expect("failed to create private_key"),
+    );
+    let address = Address::from_public_key(&private_key.to_public_key()).unwrap();
+    let other_address = Address::new(SignatureScheme::Ed25519, Hash::from([1; 32]));
+
+    let params = test_params();
+    let mut blockchain = Blockchain::new(TxOut::new(address.clone(), Amount::new(50)), params);
+    let genesis_hash = blockchain.current_hash();
+    let genesis_output = blockchain.utxos.outputs().next().unwrap().0.clone();
+
+    let mut transaction = Transaction::new(
+        vec![TxIn::new(
+            private_key.to_public_key(),
+            genesis_output.clone(),
+        )],
+        vec![
+            TxOut::new(other_address.clone(), Amount::new(20)),
+            TxOut::new(address.clone(), Amount::new(30)),
+        ],
+    );
+    let coinbase =
+        |height| Transaction::coinbase(height, TxOut::new(address.clone(), Amount::new(0)));
+    let unsigned = mined(Block::new(
+        vec![coinbase(1), transaction.clone()],
+        genesis_hash,
+        Target::MAX,
+    ));
+    assert!(matches!(
+        blockchain.verify(&unsigned),
+        Err(PrimitiveError::BadSignature { .. })
+    ));
+
+    // An input pointing past the outputs of an existing transaction is rejected without panicking
+    let mut out_of_range = Transaction::new(
+        vec![TxIn::new(
+            private_key.to_public_key(),
+            TxOutPtr::new(*genesis_output.transaction_hash(), usize::MAX),
+        )],
+        vec![TxOut::new(address.clone(), Amount::new(1))],
+    );
+    out_of_range
+        .sign_inputs(std::slice::from_ref(&private_key))
+        .expect("failed to sign inputs");
+    let out_of_range = mined(Block::new(
+        vec![coinbase(1), out_of_range],
+        genesis_hash,
+        Target::MAX,
+    ));
+    assert!(matches!(
+        blockchain.verify(&out_of_range),
+        Err(PrimitiveError::MissingInput { .. })
+    ));
+
+    transaction
+        .sign_inputs(&[private_key])
+        .expect("failed to sign inputs");
+    let mut tampered = transaction.clone();
+    tampered.outputs[0] = TxOut::new(other_address, Amount::new(30));
+    tampered.outputs[1] = TxOut::new(address.clone(), Amount::new(20));
+    let tampered = mined(Block::new(
+        vec![coinbase(1), tampered],
+        genesis_hash,
+        Target::MAX,
+    ));
+    assert!(matches!(
+        blockchain.verify(&tampered),
+        Err(PrimitiveError::BadSignature { input: 0, .. })
+    ));
+
+    let block = mined(Block::new(
+        vec![coinbase(1), transaction.clone()],
+        genesis_hash,
+        Target::MAX,
+    ));
+    let block_hash = block.hash();
+    // Outputs of the genesis block are spendable only after they mature
+    let immature = Blockchain::new(
+        TxOut::new(address.clone(), Amount::new(50)),
+        ConsensusParams {
+            coinbase_maturity: 2,
+            ..test_params()
+        },
+    );
+    assert_eq!(genesis_hash, immature.current_hash());
+    assert!(matches!(
+        immature.verify(&block),
+        Err(PrimitiveError::ImmatureCoinbase { .. })
+    ));
+    assert!(blockchain.signatures.is_empty());
+    blockchain
+        .push(block)
+        .expect("failed to push a signed transaction");
+    assert_eq!(1, blockchain.signatures.len());
+
+    let mut double_spend = Block::new(
+        vec![coinbase(2), transaction.clone()],
+        block_hash,
+        Target::MAX,
+    );
+    double_spend.set_timestamp(blockchain.median_time_past(&block_hash).unwrap() + 1);
+    let double_spend = mined(double_spend);
+    assert!(matches!(
+        blockchain.push(double_spend),
+        Err(PrimitiveError::MissingInput { .. })
+    ));
+    let twice_in_block = mined(Block::new(
+        vec![coinbase(1), transaction.clone(), transaction],
+        genesis_hash,
+        Target::MAX,
+    ));
+    assert!(matches!(
+        blockchain.push(twice_in_block),
+        Err(PrimitiveError::DuplicateTransaction { .. })
+    ));
+}
+
+#[test]
+fn test_retarget_and_timestamps() {
+    let params = test_params();
+    let mut blockchain = Blockchain::new(TxOut::new(null_address(), Amount::ZERO), params.clone());
+    let block_at = |parent_hash, timestamp, target, height| {
+        block_with(parent_hash, height, timestamp, target, Vec::new())
+    };
+
+    // Blocks are mined 4 times faster than expected
+    let mut timestamp = Block::GENESIS_TIMESTAMP;
+    for height in 1..4 {
+        timestamp += params.block_interval / 4;
+        let block = block_at(
+            blockchain.current_hash(),
+            timestamp,
+            params.pow_limit,
+            height,
+        );
+        blockchain.push(block).expect("failed to push a block");
+    }
+    timestamp += params.block_interval / 4;
+    let parent_hash = blockchain.current_hash();
+    let target = blockchain.next_target(&parent_hash).unwrap();
+    assert!(target < params.pow_limit);
+    assert!(matches!(
+        blockchain.push(block_at(parent_hash, timestamp, params.pow_limit, 4)),
+        Err(PrimitiveError::UnexpectedTarget { .. })
+    ));
+
+    // Timestamps must exceed the median of the recent past and must not be far in the future
+    let median = blockchain.median_time_past(&parent_hash).unwrap();
+    assert!(matches!(
+        blockchain.push(block_at(parent_hash, median, target, 4)),
+        Err(PrimitiveError::TimestampTooOld { .. })
+    ));
+    assert!(matches!(
+        blockchain.push(block_at(parent_hash, now() + 3 * 60 * 60, target, 4)),
+        Err(PrimitiveError::TimestampInFuture { .. })
+    ));
+    blockchain
+        .push(block_at(parent_hash, timestamp, target, 4))
+        .expect("failed to push a retargeted block");
+}
+
+#[test]
+fn test_reorganize_to_heaviest_fork() {
+    let private_key = PrivateKey::generate(SignatureScheme::Ed25519, &mut rand::thread_rng())
+        .expect("failed to create private_key");
+    let address = Address::from_public_key(&private_key.to_public_key()).unwrap();
+
+    let params = ConsensusParams {
+        retarget_interval: 100,
+        ..test_params()
+    };
+    let mut blockchain = Blockchain::new(TxOut::new(address.clone(), Amount::new(50)), params);
+    let genesis_hash = blockchain.current_hash();
+    let genesis_output = blockchain.utxos.outputs().next().unwrap().0.clone();
+    let mut transaction = Transaction::new(
+        vec![TxIn::new(
+            private_key.to_public_key(),
+            genesis_output.clone(),
+        )],
+        vec![TxOut::new(
+            Address::new(SignatureScheme::Ed25519, Hash::from([1; 32])),
+            Amount::new(50),
+        )],
+    );
+    transaction
+        .sign_inputs(&[private_key])
+        .expect("failed to sign inputs");
+    let block_at = |parent_hash, height, timestamp, transactions| {
+        let timestamp = Block::GENESIS_TIMESTAMP + timestamp;
+        block_with(parent_hash, height, timestamp, Target::MAX, transactions)
+    };
+
+    // Main chain: genesis <- a1 (spends the genesis output) <- a2
+    let a1 = block_at(genesis_hash, 1, 1, vec![transaction.clone()]);
+    let a1_hash = a1.hash();
+    blockchain.push(a1).expect("failed to push a1");
+    let a2 = block_at(a1_hash, 2, 2, Vec::new());
+    let a2_hash = a2.hash();
+    blockchain.push(a2).expect("failed to push a2");
+    assert!(blockchain.transaction(&transaction.hash()).is_some());
+
+    // Side chain: genesis <- b1 <- b2 <- b3
+    let b1 = block_at(genesis_hash, 1, 11, Vec::new());
+    let b1_hash = b1.hash();
+    let update = blockchain.push(b1).expect("failed to push b1");
+    assert!(update.connected.is_empty());
+    let b2 = block_at(b1_hash, 2, 12, Vec::new());
+    let b2_hash = b2.hash();
+    let update = blockchain.push(b2).expect("failed to push b2");
+    assert!(update.connected.is_empty());
+    assert_eq!(a2_hash, blockchain.current_hash());
+    // The genesis output is unspent on the side chain, while the main chain keeps it spent
+    blockchain
+        .verify(&block_at(b2_hash, 3, 13, vec![transaction.clone()]))
+        .expect("failed to verify a side chain block");
+    assert!(!blockchain.utxos.contains(&genesis_output));
+
+    let b3 = block_at(b2_hash, 3, 13, Vec::new());
+    let b3_hash = b3.hash();
+    let update = blockchain.push(b3).expect("failed to push b3");
+    assert_eq!(b3_hash, blockchain.current_hash());
+    assert_eq!(vec![a2_hash, a1_hash], update.disconnected);
+    assert_eq!(vec![b1_hash, b2_hash, b3_hash], update.connected);
+    assert_eq!(1, update.orphaned.len());
+    assert_eq!(transaction.hash(), update.orphaned[0].hash());
+    assert!(blockchain.transaction(&transaction.hash()).is_none());
+    assert!(blockchain.mempool().contains(&transaction.hash()));
+
+    // The orphaned transaction can be mined again on the new main chain
+    let b4 = block_at(b3_hash, 4, 14, vec![transaction]);
+    let update = blockchain.push(b4).expect("failed to push b4");
+    assert!(update.orphaned.is_empty());
+    assert!(blockchain.mempool().is_empty());
+    assert_eq!(4, blockchain.height());
+}
+
+#[test]
+fn test_connect_orphan_blocks() {
+    let mut blockchain = Blockchain::new(TxOut::new(null_address(), Amount::ZERO), test_params());
+    let block_at = |parent_hash, height| {
+        empty_block(
+            parent_hash,
+            height,
+            Block::GENESIS_TIMESTAMP + height as u64,
+        )
+    };
+    let b1 = block_at(blockchain.current_hash(), 1);
+    let b2 = block_at(b1.hash(), 2);
+    let b3 = block_at(b2.hash(), 3);
+    let (b1_hash, b2_hash, b3_hash) = (b1.hash(), b2.hash(), b3.hash());
+
+    let update = blockchain.push(b3).expect("failed to keep an orphan block");
+    assert!(update.connected.is_empty());
+    assert_eq!(vec![b2_hash], blockchain.missing_parents());
+    blockchain.push(b2).expect("failed to keep an orphan block");
+    assert_eq!(vec![b1_hash], blockchain.missing_parents());
+
+    let update = blockchain.push(b1).expect("failed to push a block");
+    assert_eq!(vec![b1_hash, b2_hash, b3_hash], update.connected);
+    assert_eq!(b3_hash, blockchain.current_hash());
+    assert!(blockchain.orphans().is_empty());
+    assert!(blockchain.missing_parents().is_empty());
+}
+
+#[test]
+fn test_block_template() {
+    let private_key = PrivateKey::generate(SignatureScheme::Ed25519, &mut rand::thread_rng())
+        .expect("failed to create private_key");
+    let address = Address::from_public_key(&private_key.to_public_key()).unwrap();
+    let other_address = Address::new(SignatureScheme::Ed25519, Hash::from([1; 32]));
+
+    let mut blockchain =
+        Blockchain::new(TxOut::new(address.clone(), Amount::new(100)), test_params());
+    let genesis_output = blockchain.utxos.outputs().next().unwrap().0.clone();
+    let spend = |source, outputs| {
+        let mut transaction = Transaction::new(
+            vec![TxIn::new(private_key.to_public_key(), source)],
+            outputs,
+        );
+        transaction
+            .sign_inputs(std::slice::from_ref(&private_key))
+            .expect("failed to sign inputs");
+        transaction
+    };
+    let parent = spend(
+        genesis_output,
+        vec![
+            TxOut::new(address.clone(), Amount::new(60)),
+            TxOut::new(address.clone(), Amount::new(25)),
+            TxOut::new(address.clone(), Amount::new(5)),
+        ],
+    );
+    let low_fee = spend(
+        TxOutPtr::new(parent.hash(), 0),
+        vec![TxOut::new(other_address.clone(), Amount::new(55))],
+    );
+    let high_fee = spend(
+        TxOutPtr::new(parent.hash(), 1),
+        vec![TxOut::new(other_address.clone(), Amount::new(5))],
+    );
+    blockchain.add_transaction(low_fee.clone()).unwrap_err();
+    for transaction in [&parent, &low_fee, &high_fee] {
+        blockchain
+            .add_transaction(transaction.clone())
+            .expect("failed to add a transaction");
+    }
+    // Signatures verified in the mempool are remembered for the block
+    assert_eq!(3, blockchain.signatures.len());
+
+    let template = blockchain
+        .block_template(other_address.clone())
+        .expect("failed to build a block template");
+    let transactions = template.body().transactions();
+    assert_eq!(Amount::new(50 + 35), transactions[0].outputs()[0].amount());
+    assert_eq!(
+        vec![parent.hash(), high_fee.hash(), low_fee.hash()],
+        transactions[1..]
+            .iter()
+            .map(Transaction::hash)
+            .collect::<Vec<_>>()
+    );
+    let timestamp = template.timestamp();
+    blockchain
+        .push(mined(template))
+        .expect("failed to push a block template");
+    assert!(blockchain.mempool().is_empty());
+
+    // Fill a block with a chain of spends, each spending the output of the previous one
+    let mut source = TxOutPtr::new(parent.hash(), 2);
+    let mut chain = move || {
+        let transaction = spend(
+            source.clone(),
+            vec![TxOut::new(address.clone(), Amount::new(5))],
+        );
+        source = TxOutPtr::new(transaction.hash(), 0);
+        transaction
+    };
+    let coinbase = Transaction::coinbase(2, TxOut::new(other_address, Amount::new(0)));
+    let mut transactions = vec![coinbase.clone()];
+    transactions.extend((0..Blockchain::TX_PER_BLOCK).map(|_| chain()));
+    let block_of = |transactions| {
+        let mut block = Block::new(transactions, blockchain.current_hash(), Target::MAX);
+        block.set_timestamp(timestamp + 1);
+        mined(block)
+    };
+    blockchain
+        .verify(&block_of(transactions.clone()))
+        .expect("failed to verify a full block");
+
+    // Only the base transaction can go without inputs, and no transaction can appear twice
+    let inputless = vec![coinbase.clone(), Transaction::new(Vec::new(), Vec::new())];
+    assert!(matches!(
+        blockchain.verify(&block_of(inputless)),
+        Err(PrimitiveError::InvalidCoinbase { .. })
+    ));
+    let repeated = vec![coinbase, transactions[1].clone(), transactions[1].clone()];
+    assert!(matches!(
+        blockchain.verify(&block_of(repeated)),
+        Err(PrimitiveError::DuplicateTransaction { .. })
+    ));
+
+    transactions.push(chain());
+    let overfull = block_of(transactions);
+    assert!(matches!(
+        blockchain.verify(&overfull),
+        Err(PrimitiveError::TooManyTransactions { .. })
+    ));
+}
+
+#[test]
+fn test_merkle_root_in_header() {
+    let mut blockchain = Blockchain::new(TxOut::new(null_address(), Amount::ZERO), test_params());
+    let coinbase = |address: u8| {
+        Transaction::coinbase(
+            1,
+            TxOut::new(
+                Address::new(SignatureScheme::Ed25519, Hash::from([address; 32])),
+                Amount::new(0),
+            ),
+        )
+    };
+    let block = mined(Block::new(
+        vec![coinbase(1)],
+        blockchain.current_hash(),
+        Target::MAX,
+    ));
+    let proof = block.merkle_proof(0).expect("failed to build a proof");
+    assert!(proof.verify(&coinbase(1).hash(), &block.desc().merkle_root()));
+    assert!(!proof.verify(&coinbase(2).hash(), &block.desc().merkle_root()));
+
+    let mut tampered = block.clone();
+    tampered.body.transactions[0] = coinbase(2);
+    assert_eq!(block.hash(), tampered.hash());
+    assert!(matches!(
+        blockchain.push(tampered),
+        Err(PrimitiveError::InvalidMerkleRoot { .. })
+    ));
+    blockchain.push(block).expect("failed to push a block");
+}
+
+#[test]
+fn test_block_encoding_roundtrip() {
+    let private_key = PrivateKey::generate(SignatureScheme::Ed25519, &mut rand::thread_rng())
+        .expect("failed to create private_key");
+    let mut transaction = Transaction::new(
+        vec![TxIn::new(
+            private_key.to_public_key(),
+            TxOutPtr::new(Hash::from([1; 32]), 3),
+        )],
+        vec![TxOut::new(
+            Address::new(SignatureScheme::Ed25519, Hash::from([2; 32])),
+            Amount::new(42),
+        )],
+    );
+    transaction
+        .sign_inputs(&[private_key])
+        .expect("failed to sign inputs");
+    let block = Block::new(
+        vec![
+            Transaction::coinbase(1, TxOut::new(null_address(), Amount::ZERO)),
+            transaction,
+        ],
+        Hash::from([3; 32]),
+        Target::MAX,
+    );
+
+    let encoded = block.encode();
+    let decoded = Block::decode(&encoded).expect("failed to decode a block");
+    assert_eq!(block.hash(), decoded.hash());
+    assert_eq!(encoded, decoded.encode());
+    assert_eq!(
+        block.body.transactions[1].hash(),
+        decoded.body.transactions[1].hash()
+    );
+
+    let mut trailing = encoded.clone();
+    trailing.push(0);
+    assert!(Block::decode(&trailing).is_err());
+    assert!(Block::decode(&encoded[..encoded.len() - 1]).is_err());
+    let desc = block.desc.encode();
+    assert_eq!(1 + 32 + 32 + 4 + 8 + 16, desc.len());
+    assert_eq!(block.hash(), BlockDesc::decode(&desc).unwrap().hash());
+}
+
+#[test]
+fn test_block_json_roundtrip() {
+    let block = Block::new(
+        vec![Transaction::new(
+            Vec::new(),
+            vec![TxOut::new(
+                Address::new(SignatureScheme::Ed25519, Hash::from([0xab; 32])),
+                Amount::new(42),
+            )],
+        )],
+        Hash::from([0xcd; 32]),
+        Target::MAX,
+    );
+    let json = serde_json::to_value(&block).expect("failed to serialize a block");
+    assert_eq!(
+        serde_json::json!("cd".repeat(32)),
+        json["desc"]["parent_hash"]
+    );
+    assert_eq!(
+        serde_json::json!(format!("01{}", "ab".repeat(32))),
+        json["body"]["transactions"][0]["outputs"][0]["receiver_address"]
+    );
+    let decoded: Block = serde_json::from_value(json).expect("failed to deserialize a block");
+    assert_eq!(block.encode(), decoded.encode());
+}
+
+#[cfg(test)]
+fn fuzz_key() -> &'static PrivateKey {
+    use std::sync::OnceLock;
+    static KEY: OnceLock<PrivateKey> = OnceLock::new();
+    KEY.get_or_init(|| {
+        PrivateKey::generate(SignatureScheme::Ed25519, &mut rand::thread_rng())
+            .expect("failed to create private_key")
+    })
+}
+
+/// Blockchain whose genesis output is owned by `fuzz_key`
+#[cfg(test)]
+fn fuzz_blockchain() -> Blockchain {
+    let address = Address::from_public_key(&fuzz_key().to_public_key()).unwrap();
+    Blockchain::new(TxOut::new(address, Amount::new(50)), test_params())
+}
+
+/// Transactions which are mostly malformed but sometimes spend the genesis output with valid signatures
+#[cfg(test)]
+fn arbitrary_transaction(
+    genesis_output: TxOutPtr,
+) -> impl proptest::strategy::Strategy<Value = Transaction> {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    let genesis_hash = *genesis_output.transaction_hash();
+    let source_output = prop_oneof![
+        Just(genesis_output),
+        any::<usize>().prop_map(move |index| TxOutPtr::new(genesis_hash, index)),
+        (any::<[u8; 32]>(), any::<usize>())
+            .prop_map(|(hash, index)| TxOutPtr::new(Hash::from(hash), index)),
+    ];
+    let input = (source_output, vec(any::<u8>(), 0..80)).prop_map(|(source_output, signature)| {
+        let mut input = TxIn::new(fuzz_key().to_public_key(), source_output);
+        input.signature = signature;
+        input
+    });
+    let amount = prop_oneof![0..100_u64, any::<u64>()].prop_map(Amount::new);
+    let output = (any::<[u8; 32]>(), amount).prop_map(|(address, amount)| {
+        TxOut::new(
+            Address::new(SignatureScheme::Ed25519, Hash::from(address)),
+            amount,
+        )
+    });
+    let coinbase_height = prop_oneof![Just(0), 0..3_u128, any::<u128>()];
+    (
+        vec(input, 0..3),
+        vec(output, 0..3),
+        coinbase_height,
+        any::<bool>(),
+    )
+        .prop_map(|(inputs, outputs, coinbase_height, signed)| {
+            let mut transaction = Transaction::new(inputs, outputs);
+            transaction.coinbase_height = coinbase_height;
+            if signed {
+                let keys = vec![fuzz_key().clone(); transaction.inputs.len()];
+                transaction
+                    .sign_inputs(&keys)
+                    .expect("failed to sign inputs");
+            }
+            transaction
+        })
+}
+
+/// Blocks on top of the genesis block whose header is mostly valid, so that the transactions are verified
+#[cfg(test)]
+fn arbitrary_block(blockchain: &Blockchain) -> impl proptest::strategy::Strategy<Value = Block> {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    let genesis_hash = blockchain.current_hash();
+    let genesis_output = blockchain.utxos.outputs().next().unwrap().0.clone();
+    let parent_hash = prop_oneof![
+        4 => Just(genesis_hash),
+        1 => any::<[u8; 32]>().prop_map(Hash::from),
+    ];
+    let bits = prop_oneof![
+        4 => Just(Target::MAX.to_compact()),
+        1 => any::<u32>(),
+    ];
+    let coinbase = proptest::option::of(any::<u64>().prop_map(|amount| {
+        Transaction::coinbase(1, TxOut::new(null_address(), Amount::new(amount % 100)))
+    }));
+    (
+        coinbase,
+        vec(arbitrary_transaction(genesis_output), 0..4),
+        parent_hash,
+        bits,
+        0..10_000_u64,
+    )
+        .prop_map(
+            move |(coinbase, mut transactions, parent_hash, bits, timestamp)| {
+                transactions.splice(0..0, coinbase);
+                let mut block = Block::new(transactions, parent_hash, Target::MAX);
+                block.desc.bits = bits;
+                block.set_timestamp(Block::GENESIS_TIMESTAMP + timestamp);
+                block
+            },
+        )
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_verify_never_panics(block in arbitrary_block(&fuzz_blockchain())) {
+        let mut blockchain = fuzz_blockchain();
+        let _ = blockchain.verify(&block);
+        let _ = blockchain.push(block);
+    }
+
+    #[test]
+    fn test_decode_and_verify_never_panic(
+        block in arbitrary_block(&fuzz_blockchain()),
+        mutations in proptest::collection::vec((proptest::prelude::any::<usize>(), proptest::prelude::any::<u8>()), 0..8),
+        truncated in proptest::prelude::any::<usize>(),
+    ) {
+        let mut encoded = block.encode();
+        for (index, byte) in mutations {
+            let len = encoded.len();
+            encoded[index % len] ^= byte;
+        }
+        let blockchain = fuzz_blockchain();
+        for bytes in [&encoded[..], &encoded[..truncated % encoded.len()]] {
+            if let Ok(block) = Block::decode(bytes) {
+                let _ = blockchain.verify(&block);
+            }
+        }
+    }
 }
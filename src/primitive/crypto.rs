@@ -1,96 +1,720 @@
-use rsa::{pkcs8::EncodePublicKey, PaddingScheme, RsaPrivateKey, RsaPublicKey};
-use sha2::{
-    digest::{consts::U32, generic_array::GenericArray},
-    Digest, Sha256,
+use curve25519_dalek::{edwards::CompressedEdwardsY, Scalar};
+use ed25519_dalek::{hazmat::ExpandedSecretKey, Signer, SigningKey, VerifyingKey};
+use rand::{CryptoRng, RngCore};
+use rayon::prelude::*;
+use rsa::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey},
+    BigUint, PaddingScheme, PublicKeyParts, RsaPrivateKey, RsaPublicKey,
 };
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256, Sha512};
+use zeroize::{Zeroize, Zeroizing};
 
-use super::Result;
-use crate::err;
+use super::Hash;
+use super::{CryptoError, PrimitiveError, Result};
+use super::{Decode, Decoder, Encode};
 
-/// 256 bit hash value
-/// TODO: consider add a trait to caluculate hash
-pub type Hash = GenericArray<u8, U32>;
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Signature scheme of a key pair, which is tagged in the encoding of public keys and addresses
+pub enum SignatureScheme {
+    #[default]
+    Ed25519,
+    /// RSA with PKCS#1 v1.5 padding, kept for compatibility with existing keys
+    Rsa,
+}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 /// Address is the hash of the account's public key
-pub struct Address(Hash);
+pub struct Address {
+    /// Scheme of the public key, which must be used to spend the outputs sent to the address
+    scheme: SignatureScheme,
+    /// Hash of the public key
+    hash: Hash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Network which an address is used on.
+/// The prefix of the string encoding differs between networks so that coins are not sent to another network.
+pub enum Network {
+    Mainnet,
+    Testnet,
+}
+
+impl SignatureScheme {
+    /// Byte identifying the scheme in encodings
+    pub fn tag(&self) -> u8 {
+        match self {
+            Self::Ed25519 => 1,
+            Self::Rsa => 2,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Result<Self> {
+        match tag {
+            1 => Ok(Self::Ed25519),
+            2 => Ok(Self::Rsa),
+            tag => Err(CryptoError::UnknownScheme { tag }.into()),
+        }
+    }
+}
+
+impl Network {
+    /// Version byte prepended to the hash in the string encoding of addresses
+    pub fn address_prefix(&self) -> u8 {
+        match self {
+            Self::Mainnet => 0x00,
+            Self::Testnet => 0x6f,
+        }
+    }
+}
+
+/// Length of the checksum appended to the string encoding of addresses
+const ADDRESS_CHECKSUM_SIZE: usize = 4;
+
+/// Length of the string encoding of addresses before Base58: network prefix, scheme tag, hash and checksum
+const ADDRESS_PAYLOAD_SIZE: usize = 1 + 1 + 32 + ADDRESS_CHECKSUM_SIZE;
+
+/// First bytes of double SHA-256 of the payload
+fn address_checksum(payload: &[u8]) -> [u8; ADDRESS_CHECKSUM_SIZE] {
+    let hash = Sha256::digest(Sha256::digest(payload));
+    [hash[0], hash[1], hash[2], hash[3]]
+}
 
 impl Address {
+    pub fn new(scheme: SignatureScheme, hash: Hash) -> Self {
+        Self { scheme, hash }
+    }
+
+    /// Address of the account owning the public key
+    pub fn from_public_key(public_key: &PublicKey) -> Result<Self> {
+        Ok(Self::new(public_key.scheme(), public_key.hash()?))
+    }
+
+    pub fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+
     pub fn as_hash(&self) -> &Hash {
-        &self.0
+        &self.hash
+    }
+
+    /// Base58Check encoding shown to users: the network prefix, the scheme tag, the hash and a 4-byte checksum
+    pub fn to_base58check(&self, network: Network) -> String {
+        let mut payload = vec![network.address_prefix(), self.scheme.tag()];
+        payload.extend_from_slice(self.hash.as_bytes());
+        let checksum = address_checksum(&payload);
+        payload.extend_from_slice(&checksum);
+        bs58::encode(payload).into_string()
+    }
+
+    /// Parse the Base58Check encoding of an address on `network`.
+    /// A mistyped character is detected by the checksum with high probability.
+    pub fn from_base58check(s: &str, network: Network) -> Result<Self> {
+        let invalid = |reason| PrimitiveError::InvalidAddress {
+            address: s.to_owned(),
+            reason,
+        };
+        let payload = bs58::decode(s)
+            .into_vec()
+            .map_err(|_| invalid("not a Base58 string"))?;
+        if payload.len() != ADDRESS_PAYLOAD_SIZE {
+            return Err(invalid("wrong length"));
+        }
+        let (payload, checksum) = payload.split_at(ADDRESS_PAYLOAD_SIZE - ADDRESS_CHECKSUM_SIZE);
+        if checksum != address_checksum(payload) {
+            return Err(invalid("checksum does not match"));
+        }
+        if payload[0] != network.address_prefix() {
+            return Err(invalid("address belongs to another network"));
+        }
+        let scheme = SignatureScheme::from_tag(payload[1])
+            .map_err(|_| invalid("unknown signature scheme"))?;
+        let hash = Hash::try_from(&payload[2..]).expect("payload has the length of a hash");
+        Ok(Self::new(scheme, hash))
     }
 }
 
 #[derive(Debug, Clone)]
 /// Public key used to verify signature of transaction input
-pub struct PublicKey(RsaPublicKey);
+pub enum PublicKey {
+    Ed25519(VerifyingKey),
+    Rsa(RsaPublicKey),
+}
 
 #[derive(Clone)]
 /// Private key used to sign transaction input
-pub struct PrivateKey(RsaPrivateKey);
+pub enum PrivateKey {
+    Ed25519(SigningKey),
+    /// Ed25519 key held as its secret scalar rather than a seed, as derived by `ExtendedPrivateKey`
+    Ed25519Expanded(Ed25519ExpandedKey),
+    Rsa(RsaPrivateKey),
+}
 
-// TODO: google about padding scheme for signatures
-const DEFAULT_PADDING_SCHEME: PaddingScheme = PaddingScheme::PKCS1v15Sign { hash: None };
+#[derive(Clone)]
+/// Ed25519 secret scalar with the prefix hashed into the nonce of each signature.
+/// Both are zeroized on drop.
+pub struct Ed25519ExpandedKey {
+    scalar: Scalar,
+    hash_prefix: [u8; 32],
+    public_key: VerifyingKey,
+}
 
-impl PublicKey {
-    pub fn new(inner: RsaPublicKey) -> Self {
-        Self(inner)
-    }
+#[derive(Debug, Clone, Copy)]
+/// Signature over a message, verified together with others by `first_invalid_signature`
+pub struct SignedMessage<'a> {
+    pub public_key: &'a PublicKey,
+    pub message: Hash,
+    pub signature: &'a [u8],
+}
+
+/// Size of RSA keys generated by `PrivateKey::generate`
+const RSA_KEY_BITS: usize = 2048;
+
+/// Padding of RSA signatures, which signs the hash without a digest prefix.
+/// This is kept as it is so that the existing RSA signatures stay valid.
+const RSA_PADDING_SCHEME: PaddingScheme = PaddingScheme::PKCS1v15Sign { hash: None };
 
-    fn inner(&self) -> &RsaPublicKey {
-        &self.0
+impl PublicKey {
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Self::Ed25519(_) => SignatureScheme::Ed25519,
+            Self::Rsa(_) => SignatureScheme::Rsa,
+        }
     }
 
+    /// Hash of the key material: the raw 32 bytes for Ed25519 and the DER encoding for RSA
     pub fn hash(&self) -> Result<Hash> {
-        let mut hasher = Sha256::new();
-        let encoded_pub_key = self
-            .inner()
-            .to_public_key_der()
-            .map_err(|e| err!("failed to encode rsa public key: {}", e))?;
-        hasher.update(encoded_pub_key);
-        Ok(hasher.finalize())
+        match self {
+            Self::Ed25519(public_key) => Ok(Hash::digest(public_key.as_bytes())),
+            Self::Rsa(public_key) => {
+                let der = public_key.to_public_key_der().map_err(CryptoError::from)?;
+                Ok(Hash::digest(der))
+            }
+        }
     }
 
     pub fn verify(&self, expected: &Hash, signed: &[u8]) -> Result<()> {
-        rsa::PublicKey::verify(&self.inner(), DEFAULT_PADDING_SCHEME, expected, signed)
-            .map_err(|e| err!("failed to verify signatured hash: {}", e))
+        match self {
+            Self::Ed25519(public_key) => {
+                let signature =
+                    ed25519_dalek::Signature::from_slice(signed).map_err(CryptoError::from)?;
+                // Strict verification rejects malleable signatures and weak keys
+                public_key
+                    .verify_strict(expected.as_bytes(), &signature)
+                    .map_err(|e| CryptoError::from(e).into())
+            }
+            Self::Rsa(public_key) => {
+                // rsa accepts short signatures and values above the modulus, which would let anyone relaying
+                // a transaction change its hash, so only the canonical encoding of the signature is accepted
+                if signed.len() != public_key.size()
+                    || BigUint::from_bytes_be(signed) >= *public_key.n()
+                {
+                    return Err(CryptoError::from(rsa::errors::Error::Verification).into());
+                }
+                rsa::PublicKey::verify(public_key, RSA_PADDING_SCHEME, expected.as_bytes(), signed)
+                    .map_err(|e| CryptoError::from(e).into())
+            }
+        }
+    }
+}
+
+impl Ed25519ExpandedKey {
+    /// Returns `None` if the scalar is zero, which has no public key
+    pub(crate) fn new(scalar: Scalar, hash_prefix: [u8; 32]) -> Option<Self> {
+        if scalar == Scalar::ZERO {
+            return None;
+        }
+        let expanded = ExpandedSecretKey {
+            scalar,
+            hash_prefix,
+        };
+        Some(Self {
+            public_key: VerifyingKey::from(&expanded),
+            scalar,
+            hash_prefix,
+        })
+    }
+
+    pub(crate) fn scalar(&self) -> &Scalar {
+        &self.scalar
+    }
+
+    pub(crate) fn hash_prefix(&self) -> &[u8; 32] {
+        &self.hash_prefix
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.public_key
+    }
+
+    fn sign(&self, message: &[u8]) -> ed25519_dalek::Signature {
+        let expanded = ExpandedSecretKey {
+            scalar: self.scalar,
+            hash_prefix: self.hash_prefix,
+        };
+        ed25519_dalek::hazmat::raw_sign::<Sha512>(&expanded, message, &self.public_key)
+    }
+}
+
+impl Drop for Ed25519ExpandedKey {
+    fn drop(&mut self) {
+        self.scalar.zeroize();
+        self.hash_prefix.zeroize();
     }
 }
 
 impl PrivateKey {
-    pub fn new(inner: RsaPrivateKey) -> Self {
-        Self(inner)
+    /// Generate a new key of the scheme
+    pub fn generate<R: CryptoRng + RngCore>(scheme: SignatureScheme, rng: &mut R) -> Result<Self> {
+        match scheme {
+            SignatureScheme::Ed25519 => Ok(Self::Ed25519(SigningKey::generate(rng))),
+            SignatureScheme::Rsa => Ok(Self::Rsa(
+                RsaPrivateKey::new(rng, RSA_KEY_BITS).map_err(CryptoError::from)?,
+            )),
+        }
     }
 
-    fn inner(&self) -> &RsaPrivateKey {
-        &self.0
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Self::Ed25519(_) | Self::Ed25519Expanded(_) => SignatureScheme::Ed25519,
+            Self::Rsa(_) => SignatureScheme::Rsa,
+        }
     }
 
     pub fn to_public_key(&self) -> PublicKey {
-        PublicKey::new(RsaPublicKey::from(self.inner()))
+        match self {
+            Self::Ed25519(private_key) => PublicKey::Ed25519(private_key.verifying_key()),
+            Self::Ed25519Expanded(private_key) => PublicKey::Ed25519(private_key.verifying_key()),
+            Self::Rsa(private_key) => PublicKey::Rsa(RsaPublicKey::from(private_key)),
+        }
+    }
+
+    /// Secret key material: the scheme tag followed by the 32 byte seed or the scalar and the prefix for Ed25519,
+    /// or the PKCS#8 DER for RSA
+    pub fn to_secret_bytes(&self) -> Result<Zeroizing<Vec<u8>>> {
+        let tag = self.scheme().tag();
+        match self {
+            Self::Ed25519(private_key) => {
+                let mut bytes =
+                    Zeroizing::new(Vec::with_capacity(1 + ed25519_dalek::SECRET_KEY_LENGTH));
+                bytes.push(tag);
+                bytes.extend_from_slice(private_key.as_bytes());
+                Ok(bytes)
+            }
+            Self::Ed25519Expanded(private_key) => {
+                let mut bytes = Zeroizing::new(Vec::with_capacity(1 + 64));
+                bytes.push(tag);
+                bytes.extend_from_slice(private_key.scalar.as_bytes());
+                bytes.extend_from_slice(&private_key.hash_prefix);
+                Ok(bytes)
+            }
+            Self::Rsa(private_key) => {
+                let der = private_key.to_pkcs8_der().map_err(CryptoError::from)?;
+                // Allocated upfront so that no copy of the key is left behind by reallocation
+                let mut bytes = Zeroizing::new(Vec::with_capacity(1 + der.as_ref().len()));
+                bytes.push(tag);
+                bytes.extend_from_slice(der.as_ref());
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Inverse of `to_secret_bytes`
+    pub fn from_secret_bytes(bytes: &[u8]) -> Result<Self> {
+        let (&tag, key) = bytes
+            .split_first()
+            .ok_or(CryptoError::MalformedPrivateKey)?;
+        match SignatureScheme::from_tag(tag)? {
+            SignatureScheme::Ed25519 if key.len() == ed25519_dalek::SECRET_KEY_LENGTH => {
+                Ok(Self::Ed25519(SigningKey::from_bytes(
+                    key.try_into().expect("length is checked"),
+                )))
+            }
+            SignatureScheme::Ed25519 if key.len() == 64 => {
+                let (scalar, hash_prefix) = key.split_at(32);
+                let scalar: Option<Scalar> =
+                    Scalar::from_canonical_bytes(scalar.try_into().expect("length is checked"))
+                        .into();
+                let expanded = scalar.and_then(|scalar| {
+                    Ed25519ExpandedKey::new(
+                        scalar,
+                        hash_prefix.try_into().expect("length is checked"),
+                    )
+                });
+                expanded
+                    .map(Self::Ed25519Expanded)
+                    .ok_or_else(|| CryptoError::MalformedPrivateKey.into())
+            }
+            SignatureScheme::Ed25519 => Err(CryptoError::MalformedPrivateKey.into()),
+            SignatureScheme::Rsa => Ok(Self::Rsa(
+                RsaPrivateKey::from_pkcs8_der(key).map_err(CryptoError::from)?,
+            )),
+        }
     }
 
     pub fn sign(&self, hash: &Hash) -> Result<Vec<u8>> {
-        self.inner()
-            .sign(DEFAULT_PADDING_SCHEME, hash)
-            .map_err(|e| err!("failed to sign by private key: {}", e))
+        match self {
+            Self::Ed25519(private_key) => Ok(private_key.sign(hash.as_bytes()).to_vec()),
+            Self::Ed25519Expanded(private_key) => Ok(private_key.sign(hash.as_bytes()).to_vec()),
+            Self::Rsa(private_key) => private_key
+                .sign(RSA_PADDING_SCHEME, hash.as_bytes())
+                .map_err(|e| CryptoError::from(e).into()),
+        }
+    }
+}
+
+/// Number of Ed25519 signatures verified in a batch.
+/// Batches differ between nodes anyway, since each node leaves out the signatures in its own `SignatureCache`,
+/// so the verdict must not depend on how the signatures are batched.
+const SIGNATURE_BATCH_SIZE: usize = 64;
+
+/// Verify the signatures in parallel, returning the index of the first invalid one.
+/// Ed25519 signatures are checked in batches, and a failed batch is checked one by one to find the invalid signature.
+pub fn first_invalid_signature(signed: &[SignedMessage]) -> Option<usize> {
+    let (ed25519, others): (Vec<usize>, Vec<usize>) =
+        (0..signed.len()).partition(|&i| signed[i].public_key.scheme() == SignatureScheme::Ed25519);
+    let is_invalid = |&i: &usize| {
+        let SignedMessage {
+            public_key,
+            message,
+            signature,
+        } = signed[i];
+        public_key.verify(&message, signature).is_err()
+    };
+    let invalid_ed25519 = ed25519
+        .par_chunks(SIGNATURE_BATCH_SIZE)
+        .filter(|batch| !verify_ed25519_batch(batch.iter().map(|&i| &signed[i])))
+        .filter_map(|batch| batch.iter().copied().find(is_invalid))
+        .min();
+    let invalid_others = others.par_iter().copied().filter(is_invalid).min();
+    invalid_ed25519.into_iter().chain(invalid_others).min()
+}
+
+/// Whether every signature in the batch passes strict verification.
+/// A failed batch is checked one signature at a time, so the batch may also fail signatures which are valid.
+///
+/// The batch checks a random linear combination of the cofactorless equations, which cancels a torsion component
+/// of `R` or of the key with a probability of up to 1/2, and the coefficients are derived from the batch itself.
+/// Strict verification rejects such signatures, so the batch is only used for points in the prime-order subgroup.
+fn verify_ed25519_batch<'a>(batch: impl Iterator<Item = &'a SignedMessage<'a>>) -> bool {
+    let mut messages = Vec::new();
+    let mut signatures = Vec::new();
+    let mut public_keys = Vec::new();
+    for signed in batch {
+        let public_key = match signed.public_key {
+            // Weak keys are rejected by strict verification
+            PublicKey::Ed25519(public_key)
+                if !public_key.is_weak() && public_key.to_edwards().is_torsion_free() =>
+            {
+                public_key
+            }
+            _ => return false,
+        };
+        let signature = match ed25519_dalek::Signature::from_slice(signed.signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        // Strict verification rejects `R` of small order, and compares `R` as encoded,
+        // so a non-canonical encoding never matches
+        let r_bytes = CompressedEdwardsY(*signature.r_bytes());
+        match r_bytes.decompress() {
+            Some(r) if r.compress() == r_bytes && !r.is_small_order() && r.is_torsion_free() => {}
+            _ => return false,
+        }
+        signatures.push(signature);
+        messages.push(signed.message.as_bytes().as_slice());
+        public_keys.push(*public_key);
+    }
+    ed25519_dalek::verify_batch(&messages, &signatures, &public_keys).is_ok()
+}
+
+impl Encode for SignatureScheme {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.tag().encode_to(buf);
+    }
+}
+
+impl Decode for SignatureScheme {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        Self::from_tag(u8::decode_from(decoder)?)
+    }
+}
+
+impl Encode for Address {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.scheme.encode_to(buf);
+        self.hash.encode_to(buf);
+    }
+}
+
+impl Decode for Address {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        Ok(Self {
+            scheme: SignatureScheme::decode_from(decoder)?,
+            hash: Hash::decode_from(decoder)?,
+        })
+    }
+}
+
+/// Addresses are represented by a single hex string of the scheme tag followed by the hash
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut encoded = Vec::new();
+        self.encode_to(&mut encoded);
+        serializer.serialize_str(&hex::encode(encoded))
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let encoded = super::serde_hex::bytes::deserialize(deserializer)?;
+        let mut decoder = Decoder::new(&encoded);
+        let address = Self::decode_from(&mut decoder).map_err(D::Error::custom)?;
+        if !decoder.is_empty() {
+            return Err(D::Error::custom("trailing bytes after address"));
+        }
+        Ok(address)
+    }
+}
+
+/// Public keys are encoded as the scheme tag followed by the key:
+/// 32 bytes for Ed25519, and the length-prefixed DER for RSA
+impl Encode for PublicKey {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        self.scheme().encode_to(buf);
+        match self {
+            Self::Ed25519(public_key) => buf.extend_from_slice(public_key.as_bytes()),
+            Self::Rsa(public_key) => {
+                // DER encoding of a valid key cannot fail
+                let encoded_pub_key = public_key
+                    .to_public_key_der()
+                    .expect("failed to encode rsa public key");
+                encoded_pub_key.as_ref().to_vec().encode_to(buf);
+            }
+        }
+    }
+}
+
+/// Non-canonical encodings are rejected so that every public key has exactly one encoding
+impl Decode for PublicKey {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        match SignatureScheme::decode_from(decoder)? {
+            SignatureScheme::Ed25519 => {
+                let bytes = decoder
+                    .read_bytes(ed25519_dalek::PUBLIC_KEY_LENGTH)?
+                    .try_into()
+                    .expect("read bytes of wrong length");
+                let public_key = VerifyingKey::from_bytes(bytes).map_err(CryptoError::from)?;
+                if public_key.as_bytes() != bytes {
+                    return Err(CryptoError::NonCanonicalKey.into());
+                }
+                Ok(Self::Ed25519(public_key))
+            }
+            SignatureScheme::Rsa => {
+                let len = decoder.read_len()?;
+                let der = decoder.read_bytes(len)?;
+                let public_key =
+                    RsaPublicKey::from_public_key_der(der).map_err(CryptoError::from)?;
+                let canonical = public_key.to_public_key_der().map_err(CryptoError::from)?;
+                if canonical.as_ref() != der {
+                    return Err(CryptoError::NonCanonicalKey.into());
+                }
+                Ok(Self::Rsa(public_key))
+            }
+        }
+    }
+}
+
+/// Public keys are represented by the hex string of their canonical encoding
+impl Serialize for PublicKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut encoded = Vec::new();
+        self.encode_to(&mut encoded);
+        serializer.serialize_str(&hex::encode(encoded))
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let encoded = super::serde_hex::bytes::deserialize(deserializer)?;
+        let mut decoder = Decoder::new(&encoded);
+        let public_key = Self::decode_from(&mut decoder).map_err(D::Error::custom)?;
+        if !decoder.is_empty() {
+            return Err(D::Error::custom("trailing bytes after public key"));
+        }
+        Ok(public_key)
     }
 }
 
 #[test]
 fn key_auth_sign_and_verify() {
-    use rand;
     let mut rng = rand::thread_rng();
-    let private_key =
-        PrivateKey::new(RsaPrivateKey::new(&mut rng, 2048).expect("failed to create private_key"));
+    for scheme in [SignatureScheme::Ed25519, SignatureScheme::Rsa] {
+        let private_key =
+            PrivateKey::generate(scheme, &mut rng).expect("failed to create private_key");
+        let public_key = private_key.to_public_key();
+        assert_eq!(scheme, public_key.scheme());
+
+        let hash = Hash::from([42; 32]);
+        let signed = private_key.sign(&hash).expect("failed to sign the hash");
+        assert!(public_key.verify(&hash, &signed).is_ok());
+        let other_hash = Hash::from([10; 32]);
+        let fake_sign = private_key
+            .sign(&other_hash)
+            .expect("failed to sign the fake hash");
+        assert!(matches!(
+            public_key.verify(&hash, &fake_sign),
+            Err(PrimitiveError::Crypto(_))
+        ));
+
+        let encoded = public_key.encode();
+        assert_eq!(scheme.tag(), encoded[1]);
+        let decoded = PublicKey::decode(&encoded).expect("failed to decode public key");
+        assert_eq!(
+            Address::from_public_key(&public_key).unwrap(),
+            Address::from_public_key(&decoded).unwrap()
+        );
+    }
+
+    // An Ed25519 signature is 64 bytes, and the key is 32 bytes after the tag
+    let private_key = PrivateKey::generate(SignatureScheme::Ed25519, &mut rng).unwrap();
+    assert_eq!(64, private_key.sign(&Hash::default()).unwrap().len());
+    assert_eq!(1 + 1 + 32, private_key.to_public_key().encode().len());
+}
+
+#[test]
+fn test_rsa_signature_malleability() {
+    let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 512).unwrap();
+    let modulus = private_key.n().clone();
+    let private_key = PrivateKey::Rsa(private_key);
     let public_key = private_key.to_public_key();
+    let hash = Hash::from([42; 32]);
+    let signed = private_key.sign(&hash).unwrap();
+    assert!(public_key.verify(&hash, &signed).is_ok());
+
+    // Other encodings of the same signature value would change the hash of the transaction
+    let zero_prefixed = [&[0][..], &signed].concat();
+    assert!(public_key.verify(&hash, &zero_prefixed).is_err());
+    let plus_modulus = (BigUint::from_bytes_be(&signed) + &modulus).to_bytes_be();
+    assert!(public_key.verify(&hash, &plus_modulus).is_err());
+}
 
-    let hash = Hash::from_slice(&[42; 32]);
-    let signed = private_key.sign(hash).expect("failed to sign the hash");
-    assert_eq!(Ok(()), public_key.verify(hash, &signed));
-    let other_hash = Hash::from_slice(&[10; 32]);
-    let fake_sign = private_key
-        .sign(other_hash)
-        .expect("failed to sign the fake hash");
-    assert_ne!(Ok(()), public_key.verify(hash, &fake_sign));
+#[test]
+fn test_address_base58check() {
+    let address = Address::new(SignatureScheme::Ed25519, Hash::from([7; 32]));
+    let encoded = address.to_base58check(Network::Mainnet);
+    assert_eq!(
+        address,
+        Address::from_base58check(&encoded, Network::Mainnet).unwrap()
+    );
+    assert_ne!(encoded, address.to_base58check(Network::Testnet));
+    assert_ne!(
+        encoded,
+        Address::new(SignatureScheme::Rsa, Hash::from([7; 32])).to_base58check(Network::Mainnet)
+    );
+    assert!(matches!(
+        Address::from_base58check(&encoded, Network::Testnet),
+        Err(PrimitiveError::InvalidAddress { .. })
+    ));
+
+    // Every single-character typo is caught
+    let alphabet = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    for (i, original) in encoded.char_indices() {
+        for typo in alphabet.chars().filter(|c| *c != original) {
+            let mut mistyped = encoded.clone();
+            mistyped.replace_range(i..i + 1, &typo.to_string());
+            assert!(Address::from_base58check(&mistyped, Network::Mainnet).is_err());
+        }
+    }
+    assert!(Address::from_base58check("0OIl", Network::Mainnet).is_err());
+    assert!(Address::from_base58check(&encoded[1..], Network::Mainnet).is_err());
+}
+
+#[test]
+fn test_first_invalid_signature() {
+    let mut rng = rand::thread_rng();
+    let ed25519 = PrivateKey::generate(SignatureScheme::Ed25519, &mut rng).unwrap();
+    let rsa = PrivateKey::Rsa(RsaPrivateKey::new(&mut rng, 512).unwrap());
+    // More Ed25519 signatures than a batch, with some RSA signatures mixed in
+    let keys: Vec<_> = (0..100)
+        .map(|i| if i % 10 == 0 { &rsa } else { &ed25519 })
+        .collect();
+    let public_keys: Vec<_> = keys.iter().map(|key| key.to_public_key()).collect();
+    let messages: Vec<_> = (0..100).map(|i| Hash::from([i; 32])).collect();
+    let signatures: Vec<_> = keys
+        .iter()
+        .zip(&messages)
+        .map(|(key, message)| key.sign(message).unwrap())
+        .collect();
+    let first_invalid = |signatures: &[Vec<u8>]| -> Option<usize> {
+        let signed: Vec<_> = (0..100)
+            .map(|i| SignedMessage {
+                public_key: &public_keys[i],
+                message: messages[i],
+                signature: &signatures[i],
+            })
+            .collect();
+        first_invalid_signature(&signed)
+    };
+    assert_eq!(None, first_invalid(&signatures));
+
+    let mut tampered = signatures.clone();
+    tampered[99] = Vec::new();
+    assert_eq!(Some(99), first_invalid(&tampered));
+    for invalid in [3, 40, 70] {
+        let mut tampered = tampered.clone();
+        tampered[invalid][0] ^= 1;
+        assert_eq!(Some(invalid), first_invalid(&tampered));
+    }
+}
+
+#[test]
+fn test_batch_rejects_torsion() {
+    use curve25519_dalek::{constants::EIGHT_TORSION, EdwardsPoint};
+    let mut rng = rand::thread_rng();
+    let signing_key = SigningKey::generate(&mut rng);
+    let public_key = PublicKey::Ed25519(signing_key.verifying_key());
+    // Signature whose `R` is `nonce * B + torsion`, which satisfies the cofactorless equation up to the torsion
+    let forge = |message: &Hash, nonce: Scalar, torsion: EdwardsPoint| {
+        let r = (EdwardsPoint::mul_base(&nonce) + torsion).compress();
+        let k = Sha512::new()
+            .chain_update(r.as_bytes())
+            .chain_update(signing_key.verifying_key().as_bytes())
+            .chain_update(message.as_bytes())
+            .finalize();
+        let s = nonce + Scalar::from_bytes_mod_order_wide(&k.into()) * signing_key.to_scalar();
+        [r.to_bytes(), s.to_bytes()].concat()
+    };
+    let messages: Vec<_> = (0..8).map(|i| Hash::from([i; 32])).collect();
+    let valid: Vec<_> = messages
+        .iter()
+        .map(|message| signing_key.sign(message.as_bytes()).to_vec())
+        .collect();
+    let random_nonce = Scalar::from_bytes_mod_order(rand::Rng::gen(&mut rng));
+    for (forged, nonce, torsion) in [
+        // `R` of small order
+        (3, Scalar::ZERO, EdwardsPoint::default()),
+        (5, Scalar::ZERO, EIGHT_TORSION[4]),
+        // `R` with a torsion component, which the batch cancels for some coefficients
+        (0, random_nonce, EIGHT_TORSION[4]),
+        (2, random_nonce, EIGHT_TORSION[2]),
+        (7, random_nonce, EIGHT_TORSION[1]),
+    ] {
+        let mut signatures = valid.clone();
+        signatures[forged] = forge(&messages[forged], nonce, torsion);
+        assert!(public_key
+            .verify(&messages[forged], &signatures[forged])
+            .is_err());
+        let signed: Vec<_> = messages
+            .iter()
+            .zip(&signatures)
+            .map(|(message, signature)| SignedMessage {
+                public_key: &public_key,
+                message: *message,
+                signature,
+            })
+            .collect();
+        assert_eq!(Some(forged), first_invalid_signature(&signed));
+    }
 }
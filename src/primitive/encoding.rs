@@ -0,0 +1,178 @@
+use std::mem::size_of;
+
+use super::Hash;
+use super::{PrimitiveError, Result};
+
+/// Version of the binary format, written at the head of every encoded value
+pub const ENCODING_VERSION: u8 = 1;
+
+/// Canonical binary encoding.
+/// Integers are little-endian with fixed width, `usize` is always 64 bits wide, and variable length
+/// values are prefixed with their length as `u32`, so that every platform produces the same bytes.
+pub trait Encode {
+    /// Append the encoding of the value without the version
+    fn encode_to(&self, buf: &mut Vec<u8>);
+
+    /// Encode the value prefixed with the version of the format
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![ENCODING_VERSION];
+        self.encode_to(&mut buf);
+        buf
+    }
+}
+
+/// Strict decoding of the canonical binary encoding
+pub trait Decode: Sized {
+    /// Read the value without the version
+    fn decode_from(decoder: &mut Decoder) -> Result<Self>;
+
+    /// Decode the value prefixed with the version of the format, rejecting any trailing bytes
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut decoder = Decoder::new(bytes);
+        let version = u8::decode_from(&mut decoder)?;
+        if version != ENCODING_VERSION {
+            return Err(PrimitiveError::Encoding(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        let value = Self::decode_from(&mut decoder)?;
+        if !decoder.is_empty() {
+            return Err(PrimitiveError::Encoding(
+                "trailing bytes after encoded value".to_owned(),
+            ));
+        }
+        Ok(value)
+    }
+}
+
+#[derive(Debug)]
+/// Cursor over the bytes being decoded
+pub struct Decoder<'bytes> {
+    /// Bytes which have not been read yet
+    bytes: &'bytes [u8],
+}
+
+impl<'bytes> Decoder<'bytes> {
+    pub fn new(bytes: &'bytes [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Read exactly `len` bytes
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'bytes [u8]> {
+        if self.bytes.len() < len {
+            return Err(PrimitiveError::Encoding(
+                "unexpected end of encoded bytes".to_owned(),
+            ));
+        }
+        let (read, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(read)
+    }
+
+    /// Read the length prefix of a variable length value
+    pub fn read_len(&mut self) -> Result<usize> {
+        let len = u32::decode_from(self)? as usize;
+        // Every element takes at least one byte, so a longer length cannot be valid
+        if len > self.bytes.len() {
+            return Err(PrimitiveError::Encoding(
+                "encoded length exceeds the remaining bytes".to_owned(),
+            ));
+        }
+        Ok(len)
+    }
+}
+
+/// Write the length prefix of a variable length value
+pub fn encode_len(len: usize, buf: &mut Vec<u8>) {
+    u32::try_from(len)
+        .expect("length of encoded value exceeds u32")
+        .encode_to(buf);
+}
+
+macro_rules! impl_int_encoding {
+    ($($int:ty),+) => {
+        $(
+            impl Encode for $int {
+                fn encode_to(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $int {
+                fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+                    let bytes = decoder.read_bytes(size_of::<$int>())?;
+                    Ok(<$int>::from_le_bytes(
+                        bytes.try_into().expect("read bytes of wrong length"),
+                    ))
+                }
+            }
+        )+
+    };
+}
+
+impl_int_encoding!(u8, u32, u64, u128);
+
+impl Encode for usize {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        (*self as u64).encode_to(buf);
+    }
+}
+
+impl Decode for usize {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        usize::try_from(u64::decode_from(decoder)?).map_err(|_| {
+            PrimitiveError::Encoding("encoded integer does not fit in usize".to_owned())
+        })
+    }
+}
+
+impl Encode for Hash {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for Hash {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        let bytes = decoder.read_bytes(32)?;
+        Ok(Hash::try_from(bytes).expect("read bytes of wrong length"))
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        encode_len(self.len(), buf);
+        for item in self {
+            item.encode_to(buf);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode_from(decoder: &mut Decoder) -> Result<Self> {
+        let len = decoder.read_len()?;
+        (0..len).map(|_| T::decode_from(decoder)).collect()
+    }
+}
+
+#[test]
+fn test_strict_decoding() {
+    let value: Vec<u64> = vec![1, 2, u64::MAX];
+    let encoded = value.encode();
+    assert_eq!(1 + 4 + 3 * 8, encoded.len());
+    assert_eq!(value, Vec::<u64>::decode(&encoded).unwrap());
+
+    let mut trailing = encoded.clone();
+    trailing.push(0);
+    assert!(Vec::<u64>::decode(&trailing).is_err());
+    assert!(Vec::<u64>::decode(&encoded[..encoded.len() - 1]).is_err());
+    let mut wrong_version = encoded;
+    wrong_version[0] = ENCODING_VERSION + 1;
+    assert!(Vec::<u64>::decode(&wrong_version).is_err());
+    assert!(Vec::<u8>::decode(&[ENCODING_VERSION, 0xff, 0xff, 0xff, 0xff]).is_err());
+}
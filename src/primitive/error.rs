@@ -1,16 +1,144 @@
 use thiserror::Error;
 
-#[derive(Debug, Error, PartialEq)]
+use super::{Amount, Hash, TxOutPtr};
+
+#[derive(Debug, Error)]
+/// Reason why a block, a transaction or an encoded value is rejected
 pub enum PrimitiveError {
-    #[error("{}", 0)]
-    Uncertain(String),
+    #[error("malformed encoding: {0}")]
+    Encoding(String),
+    #[error("invalid amount {amount:?}: {reason}")]
+    InvalidAmount {
+        amount: String,
+        reason: &'static str,
+    },
+    #[error("invalid address {address:?}: {reason}")]
+    InvalidAddress {
+        address: String,
+        reason: &'static str,
+    },
+    #[error("invalid derivation path {path:?}: {reason}")]
+    InvalidDerivationPath { path: String, reason: &'static str },
+    #[error("invalid hash {hash:?}: expected 64 hex digits")]
+    InvalidHash { hash: String },
+    #[error("compact target {bits:#010x} is negative or overflowed")]
+    InvalidTarget { bits: u32 },
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+    #[error(transparent)]
+    Keystore(#[from] KeystoreError),
+    #[error("{keys} private keys are given to sign {inputs} inputs")]
+    KeyCountMismatch { keys: usize, inputs: usize },
+
+    #[error("block {block} already exists")]
+    DuplicateBlock { block: Hash },
+    /// The block is not known to the blockchain
+    #[error("block {block} is not found")]
+    UnknownBlock { block: Hash },
+    #[error("parent {parent} of block {block} is not found")]
+    UnknownParent { block: Hash, parent: Hash },
+    #[error("block {block} does not meet the difficulty of PoW")]
+    InvalidPow { block: Hash },
+    #[error("block {block} declares target {bits:#010x} instead of {expected:#010x}")]
+    UnexpectedTarget {
+        block: Hash,
+        bits: u32,
+        expected: u32,
+    },
+    #[error("block {block} is not newer than the median time {median_time_past} of the preceding blocks")]
+    TimestampTooOld { block: Hash, median_time_past: u64 },
+    #[error("timestamp {timestamp} of block {block} is too far in the future")]
+    TimestampInFuture { block: Hash, timestamp: u64 },
+    #[error("block {block} contains {count} transactions")]
+    TooManyTransactions { block: Hash, count: usize },
+    #[error("Merkle root of block {block} does not match with its transactions")]
+    InvalidMerkleRoot { block: Hash },
+    #[error("invalid base transaction in block {block}: {reason}")]
+    InvalidCoinbase { block: Hash, reason: &'static str },
+    #[error("block {block} claims {claimed} while the subsidy and the fees are {allowed}")]
+    ExcessiveReward {
+        block: Hash,
+        claimed: Amount,
+        allowed: Amount,
+    },
+    /// The subsidy and the fees of the block on top of `parent` exceed the maximum money supply
+    #[error("reward of the block on top of {parent} exceeded the maximum money supply")]
+    RewardOverflow { parent: Hash },
+
+    /// The output is neither unspent nor created by a preceding transaction
+    #[error("output {output} spent by transaction {transaction} is not unspent")]
+    MissingInput { transaction: Hash, output: TxOutPtr },
+    /// The output is spent twice in the transaction, the block or the mempool
+    #[error("output {output} is spent twice by transaction {transaction}")]
+    DoubleSpend { transaction: Hash, output: TxOutPtr },
+    #[error("output {output} of base transaction is spent by transaction {transaction} before it matures")]
+    ImmatureCoinbase { transaction: Hash, output: TxOutPtr },
+    /// The input is not signed by the owner of the output it spends
+    #[error("input {input} of transaction {transaction} is not signed by the owner of its source")]
+    BadSignature { transaction: Hash, input: usize },
+    #[error("transaction {transaction} spends {input} but creates {output}")]
+    Unbalanced {
+        transaction: Hash,
+        input: Amount,
+        output: Amount,
+    },
+    #[error("amount of transaction {transaction} exceeded the maximum money supply")]
+    AmountOverflow { transaction: Hash },
+    /// The transaction appears twice in the block, or its outputs collide with the unspent outputs of an identical transaction
+    #[error("outputs of transaction {transaction} already exist")]
+    DuplicateTransaction { transaction: Hash },
+    /// The transaction is valid but the mempool does not keep it
+    #[error("transaction {transaction} is rejected by mempool: {reason}")]
+    MempoolRejected {
+        transaction: Hash,
+        reason: &'static str,
+    },
 }
 
-pub type Result<T> = std::result::Result<T, PrimitiveError>;
+#[derive(Debug, Error)]
+/// Failure of a cryptographic operation
+pub enum CryptoError {
+    #[error("ed25519 operation failed: {0}")]
+    Ed25519(#[from] ed25519_dalek::SignatureError),
+    #[error("rsa operation failed: {0}")]
+    Rsa(#[from] rsa::errors::Error),
+    #[error("failed to encode or decode rsa public key: {0}")]
+    PublicKeyDer(#[from] rsa::pkcs8::spki::Error),
+    #[error("failed to encode or decode rsa private key: {0}")]
+    PrivateKeyDer(#[from] rsa::pkcs8::Error),
+    #[error("private key is malformed")]
+    MalformedPrivateKey,
+    #[error("invalid seed phrase: {0}")]
+    SeedPhrase(#[from] bip39::Error),
+    #[error("hardened child {index} cannot be derived from a public key")]
+    HardenedDerivation { index: u32 },
+    /// The derived key is unusable, which happens with negligible probability; skip to the next index
+    #[error("child {index} does not make a valid key")]
+    InvalidChildKey { index: u32 },
+    #[error("public key is not canonically encoded")]
+    NonCanonicalKey,
+    #[error("unknown signature scheme {tag}")]
+    UnknownScheme { tag: u8 },
+}
 
-#[macro_export]
-macro_rules! err {
-    ($($e:expr),+) => {
-        crate::primitive::PrimitiveError::Uncertain(format!($($e),+))
-    };
+#[derive(Debug, Error)]
+/// Failure to read, write or unlock a key store
+pub enum KeystoreError {
+    #[error("failed to access key store: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed key store: {0}")]
+    Format(#[from] serde_json::Error),
+    #[error("unsupported key store version {version}")]
+    UnsupportedVersion { version: u32 },
+    #[error("failed to derive key from passphrase: {0}")]
+    Kdf(argon2::Error),
+    /// Decryption failed, which means the passphrase is wrong or the key store has been tampered with
+    #[error("failed to decrypt key {name:?}: wrong passphrase or corrupted key store")]
+    WrongPassphrase { name: String },
+    #[error("key {name:?} already exists")]
+    DuplicateKey { name: String },
+    #[error("key {name:?} is not found")]
+    UnknownKey { name: String },
 }
+
+pub type Result<T> = std::result::Result<T, PrimitiveError>;
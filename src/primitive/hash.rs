@@ -0,0 +1,147 @@
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{digest::Output, Digest, Sha256};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use subtle::ConstantTimeEq;
+
+use super::Encode;
+use super::{PrimitiveError, Result};
+
+#[derive(Clone, Copy, Default)]
+/// 256 bit hash value, which is formatted and parsed as a lowercase hex string.
+/// Hashes are compared in constant time.
+pub struct Hash([u8; 32]);
+
+/// Values identified by a hash, which is SHA-256 of their canonical encoding unless overridden
+pub trait Hashable: Encode {
+    fn hash(&self) -> Hash {
+        Hash::digest(self.encode())
+    }
+}
+
+impl Hash {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// SHA-256 of the data
+    pub fn digest(data: impl AsRef<[u8]>) -> Self {
+        Self::from(Sha256::digest(data))
+    }
+}
+
+impl From<[u8; 32]> for Hash {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Output<Sha256>> for Hash {
+    fn from(output: Output<Sha256>) -> Self {
+        Self(output.into())
+    }
+}
+
+impl TryFrom<&[u8]> for Hash {
+    type Error = std::array::TryFromSliceError;
+
+    fn try_from(bytes: &[u8]) -> std::result::Result<Self, Self::Error> {
+        Ok(Self(bytes.try_into()?))
+    }
+}
+
+impl AsRef<[u8]> for Hash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq for Hash {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+impl Eq for Hash {}
+
+impl std::hash::Hash for Hash {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+/// Hashes are ordered as big-endian numbers
+impl Ord for Hash {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl PartialOrd for Hash {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::LowerHex for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hash({})", self)
+    }
+}
+
+/// Parses exactly 64 hex digits in either case
+impl FromStr for Hash {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(s, &mut bytes)
+            .map_err(|_| PrimitiveError::InvalidHash { hash: s.to_owned() })?;
+        Ok(Self(bytes))
+    }
+}
+
+/// Hashes are represented by their hex string
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+#[test]
+fn test_hash_hex() {
+    let hash = Hash::digest(b"abc");
+    let hex = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    assert_eq!(hex, hash.to_string());
+    assert_eq!(hash, hex.parse().unwrap());
+    assert_eq!(hash, hex.to_uppercase().parse().unwrap());
+    assert!(hex[1..].parse::<Hash>().is_err());
+    assert!(format!("{}0", hex).parse::<Hash>().is_err());
+    assert!(hex.replace('a', "g").parse::<Hash>().is_err());
+
+    assert!(Hash::from([0; 32]) < Hash::from([1; 32]));
+    let json = serde_json::to_string(&hash).unwrap();
+    assert_eq!(format!("\"{}\"", hex), json);
+    assert_eq!(hash, serde_json::from_str(&json).unwrap());
+}
@@ -0,0 +1,379 @@
+use bip39::{Language, Mnemonic};
+use curve25519_dalek::{EdwardsPoint, Scalar};
+use ed25519_dalek::VerifyingKey;
+use hmac::{Hmac, Mac};
+use rand::{CryptoRng, RngCore};
+use sha2::Sha512;
+use std::fmt;
+use std::str::FromStr;
+use zeroize::{Zeroize, Zeroizing};
+
+use super::{CryptoError, Ed25519ExpandedKey, PrimitiveError, PrivateKey, PublicKey, Result};
+
+/// Child indices at and above this are hardened, which cannot be derived from a public key
+pub const HARDENED: u32 = 1 << 31;
+
+/// HMAC key deriving the root key from a seed
+const SEED_KEY: &[u8] = b"tipchune hd seed";
+
+#[derive(Clone)]
+/// BIP-39 seed phrase from which every key of a wallet is derived.
+/// The last word carries a checksum, so mistyped phrases are rejected.
+pub struct SeedPhrase(Mnemonic);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Child indices from the root key to a descendant, written like `m/0'/1` where `'` marks a hardened index
+pub struct DerivationPath(Vec<u32>);
+
+#[derive(Clone)]
+/// Private key which derives child keys, together with its chain code.
+/// Keys are Ed25519 scalars, so that a child public key is the parent public key plus a public tweak.
+pub struct ExtendedPrivateKey {
+    key: Ed25519ExpandedKey,
+    chain_code: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Public half of an extended private key, which derives the public keys of non-hardened children.
+/// This lets a watch-only wallet find the addresses of the wallet without its private keys.
+pub struct ExtendedPublicKey {
+    public_key: VerifyingKey,
+    chain_code: [u8; 32],
+}
+
+impl SeedPhrase {
+    /// Number of words of generated phrases, which encode 256 bits of entropy
+    pub const WORD_COUNT: usize = 24;
+
+    pub fn generate<R: CryptoRng + RngCore>(rng: &mut R) -> Self {
+        Self(
+            Mnemonic::generate_in_with(rng, Language::English, Self::WORD_COUNT)
+                .expect("word count is valid"),
+        )
+    }
+
+    /// Seed stretched from the phrase and an optional passphrase, which is empty if unused
+    pub fn to_seed(&self, passphrase: &str) -> Zeroizing<[u8; 64]> {
+        Zeroizing::new(self.0.to_seed(passphrase))
+    }
+}
+
+impl FromStr for SeedPhrase {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(Self(
+            Mnemonic::parse_in(Language::English, s).map_err(CryptoError::from)?,
+        ))
+    }
+}
+
+impl fmt::Display for SeedPhrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl DerivationPath {
+    pub fn new(indices: Vec<u32>) -> Self {
+        Self(indices)
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.0
+    }
+
+    /// Path to the child at `index` of the key at this path
+    pub fn child(&self, index: u32) -> Self {
+        let mut indices = self.0.clone();
+        indices.push(index);
+        Self(indices)
+    }
+}
+
+/// Parses `m` followed by `/`-separated indices, where hardened indices end with `'` or `h`
+impl FromStr for DerivationPath {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason| PrimitiveError::InvalidDerivationPath {
+            path: s.to_owned(),
+            reason,
+        };
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(invalid("path does not start with m"));
+        }
+        parts
+            .map(|part| {
+                let (digits, offset) = match part.strip_suffix(['\'', 'h']) {
+                    Some(digits) => (digits, HARDENED),
+                    None => (part, 0),
+                };
+                // Signs and leading zeros would give an index more than one way of writing
+                if digits.is_empty()
+                    || !digits.bytes().all(|b| b.is_ascii_digit())
+                    || (digits.len() > 1 && digits.starts_with('0'))
+                {
+                    return Err(invalid("index is not a decimal number"));
+                }
+                match digits.parse::<u32>() {
+                    Ok(index) if index < HARDENED => Ok(index + offset),
+                    _ => Err(invalid("index is too large")),
+                }
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("m")?;
+        for &index in &self.0 {
+            if index >= HARDENED {
+                write!(f, "/{}'", index - HARDENED)?;
+            } else {
+                write!(f, "/{}", index)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ExtendedPrivateKey {
+    /// Root key of the wallet whose seed is `seed`
+    pub fn from_seed(seed: &[u8]) -> Result<Self> {
+        let scalar = Scalar::from_bytes_mod_order_wide(&hmac_sha512(SEED_KEY, &[seed, &[0]]));
+        let rest = hmac_sha512(SEED_KEY, &[seed, &[1]]);
+        let (hash_prefix, chain_code) = split(&rest);
+        let key = Ed25519ExpandedKey::new(scalar, hash_prefix)
+            .ok_or(CryptoError::InvalidChildKey { index: 0 })?;
+        Ok(Self { key, chain_code })
+    }
+
+    /// Root key of the wallet backed up as the seed phrase, protected by an optional passphrase
+    pub fn from_seed_phrase(phrase: &SeedPhrase, passphrase: &str) -> Result<Self> {
+        Self::from_seed(phrase.to_seed(passphrase).as_ref())
+    }
+
+    /// Child key at `index`, where indices at and above `HARDENED` are hardened.
+    /// A non-hardened child has the same public key as the child derived by the extended public key,
+    /// so the private key of a non-hardened child and the chain code reveal the parent private key.
+    pub fn derive_child(&self, index: u32) -> Result<Self> {
+        let index_bytes = index.to_be_bytes();
+        let public_key = self.key.verifying_key();
+        let (tweak, chain_code) = if index >= HARDENED {
+            let data: [&[u8]; 4] = [
+                &[0],
+                self.key.scalar().as_bytes(),
+                self.key.hash_prefix(),
+                &index_bytes,
+            ];
+            child_tweak(&self.chain_code, &data)
+        } else {
+            child_tweak(
+                &self.chain_code,
+                &[&[2], public_key.as_bytes(), &index_bytes],
+            )
+        };
+        let (hash_prefix, _) = split(&hmac_sha512(self.key.hash_prefix(), &[&index_bytes]));
+        let key = Ed25519ExpandedKey::new(self.key.scalar() + tweak, hash_prefix)
+            .ok_or(CryptoError::InvalidChildKey { index })?;
+        Ok(Self { key, chain_code })
+    }
+
+    /// Descendant at the path from this key
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self> {
+        path.indices()
+            .iter()
+            .try_fold(self.clone(), |key, &index| key.derive_child(index))
+    }
+
+    pub fn private_key(&self) -> PrivateKey {
+        PrivateKey::Ed25519Expanded(self.key.clone())
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::Ed25519(self.key.verifying_key())
+    }
+
+    pub fn to_extended_public_key(&self) -> ExtendedPublicKey {
+        ExtendedPublicKey {
+            public_key: self.key.verifying_key(),
+            chain_code: self.chain_code,
+        }
+    }
+}
+
+/// The chain code is secret along with the key, since it derives the keys of hardened children
+impl Drop for ExtendedPrivateKey {
+    fn drop(&mut self) {
+        self.chain_code.zeroize();
+    }
+}
+
+impl ExtendedPublicKey {
+    /// Public key of the child at `index`, which must not be hardened
+    pub fn derive_child(&self, index: u32) -> Result<Self> {
+        if index >= HARDENED {
+            return Err(CryptoError::HardenedDerivation { index }.into());
+        }
+        let (tweak, chain_code) = child_tweak(
+            &self.chain_code,
+            &[&[2], self.public_key.as_bytes(), &index.to_be_bytes()],
+        );
+        let point = self.public_key.to_edwards() + EdwardsPoint::mul_base(&tweak);
+        if point.is_small_order() {
+            return Err(CryptoError::InvalidChildKey { index }.into());
+        }
+        Ok(Self {
+            public_key: VerifyingKey::from(point),
+            chain_code,
+        })
+    }
+
+    /// Descendant at the path from this key, which must not contain hardened indices
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self> {
+        path.indices()
+            .iter()
+            .try_fold(self.clone(), |key, &index| key.derive_child(index))
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::Ed25519(self.public_key)
+    }
+}
+
+/// Extended public keys are exported as the hex string of the public key followed by the chain code
+impl fmt::Display for ExtendedPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            hex::encode(self.public_key.as_bytes()),
+            hex::encode(self.chain_code)
+        )
+    }
+}
+
+impl FromStr for ExtendedPublicKey {
+    type Err = PrimitiveError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut bytes = [0; 64];
+        hex::decode_to_slice(s, &mut bytes).map_err(|e| {
+            PrimitiveError::Encoding(format!("extended public key is not 128 hex digits: {}", e))
+        })?;
+        let (public_key, chain_code) = split(&bytes);
+        let verifying_key = VerifyingKey::from_bytes(&public_key).map_err(CryptoError::from)?;
+        if verifying_key.as_bytes() != &public_key {
+            return Err(CryptoError::NonCanonicalKey.into());
+        }
+        Ok(Self {
+            public_key: verifying_key,
+            chain_code,
+        })
+    }
+}
+
+/// Tweak added to the parent key and the chain code of the child identified by `data`
+fn child_tweak(chain_code: &[u8; 32], data: &[&[u8]]) -> (Scalar, [u8; 32]) {
+    let tweak =
+        Scalar::from_bytes_mod_order_wide(&hmac_sha512(chain_code, &[data, &[&[0]]].concat()));
+    let (chain_code, _) = split(&hmac_sha512(chain_code, &[data, &[&[1]]].concat()));
+    (tweak, chain_code)
+}
+
+fn hmac_sha512(key: &[u8], parts: &[&[u8]]) -> Zeroizing<[u8; 64]> {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    Zeroizing::new(mac.finalize().into_bytes().into())
+}
+
+fn split(bytes: &[u8; 64]) -> ([u8; 32], [u8; 32]) {
+    let (left, right) = bytes.split_at(32);
+    (
+        left.try_into().expect("half of 64 bytes"),
+        right.try_into().expect("half of 64 bytes"),
+    )
+}
+
+#[test]
+fn test_hd_key_derivation() {
+    use super::{Address, Hash};
+    let words = "abandon ".repeat(11);
+    let phrase: SeedPhrase = format!("{}about", words)
+        .parse()
+        .expect("failed to parse a seed phrase");
+    // The last word does not match the checksum
+    assert!(format!("{}abandon", words).parse::<SeedPhrase>().is_err());
+    let generated = SeedPhrase::generate(&mut rand::thread_rng());
+    assert_eq!(
+        SeedPhrase::WORD_COUNT,
+        generated.to_string().split(' ').count()
+    );
+    assert_eq!(
+        generated.to_seed("").as_ref(),
+        generated
+            .to_string()
+            .parse::<SeedPhrase>()
+            .unwrap()
+            .to_seed("")
+            .as_ref()
+    );
+
+    let path: DerivationPath = "m/44'/0h/7".parse().unwrap();
+    assert_eq!(vec![HARDENED + 44, HARDENED, 7], path.indices());
+    assert_eq!("m/44'/0'/7", path.to_string());
+    for invalid in ["", "44'/0", "m/", "m/-1", "m/01", "m/2147483648", "m/1''"] {
+        assert!(invalid.parse::<DerivationPath>().is_err(), "{}", invalid);
+    }
+
+    // Restoring from the phrase gives the same keys, and the passphrase gives another wallet
+    let root = ExtendedPrivateKey::from_seed_phrase(&phrase, "").unwrap();
+    let restored = ExtendedPrivateKey::from_seed_phrase(&phrase, "").unwrap();
+    let protected = ExtendedPrivateKey::from_seed_phrase(&phrase, "passphrase").unwrap();
+    let address = |key: PublicKey| Address::from_public_key(&key).unwrap();
+    let account_path: DerivationPath = "m/44'/0'".parse().unwrap();
+    let account = root.derive_path(&account_path).unwrap();
+    assert_eq!(
+        address(account.public_key()),
+        address(restored.derive_path(&account_path).unwrap().public_key())
+    );
+    assert_ne!(address(root.public_key()), address(protected.public_key()));
+    assert_ne!(
+        address(account.derive_child(0).unwrap().public_key()),
+        address(account.derive_child(1).unwrap().public_key())
+    );
+
+    // A watch-only wallet derives the same public keys for non-hardened children
+    let watch_only: ExtendedPublicKey = account
+        .to_extended_public_key()
+        .to_string()
+        .parse()
+        .unwrap();
+    assert_eq!(account.to_extended_public_key(), watch_only);
+    let receiving = DerivationPath::new(vec![0, 5]);
+    let child = account.derive_path(&receiving).unwrap();
+    assert_eq!(
+        address(child.public_key()),
+        address(watch_only.derive_path(&receiving).unwrap().public_key())
+    );
+    assert!(matches!(
+        watch_only.derive_child(HARDENED),
+        Err(PrimitiveError::Crypto(
+            CryptoError::HardenedDerivation { .. }
+        ))
+    ));
+
+    // Derived keys sign like any other key, and survive being stored as secret bytes
+    let private_key = child.private_key();
+    let hash = Hash::from([3; 32]);
+    let signature = private_key.sign(&hash).unwrap();
+    assert!(child.public_key().verify(&hash, &signature).is_ok());
+    let stored = PrivateKey::from_secret_bytes(&private_key.to_secret_bytes().unwrap()).unwrap();
+    assert_eq!(signature, stored.sign(&hash).unwrap());
+}
@@ -0,0 +1,296 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+use super::serde_hex;
+use super::{Encode, PrivateKey, PublicKey};
+use super::{KeystoreError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Cost of Argon2id deriving the encryption key of a private key from its passphrase
+pub struct KdfParams {
+    /// Memory used in KiB
+    pub memory_kib: u32,
+    /// Argon2 passes over memory
+    pub iterations: u32,
+    /// Argon2 lanes
+    pub parallelism: u32,
+}
+
+#[derive(Debug)]
+/// Private keys encrypted with passphrases and saved in a JSON file.
+/// Each key is encrypted with ChaCha20-Poly1305 under a key derived from its passphrase by Argon2id.
+pub struct Keystore {
+    path: PathBuf,
+    /// Cost of deriving the encryption key of keys imported from now on
+    kdf: KdfParams,
+    keys: BTreeMap<String, EncryptedKey>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// Content of the key store file
+struct KeystoreFile {
+    version: u32,
+    keys: BTreeMap<String, EncryptedKey>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Private key encrypted with a passphrase, which is listed by its public key without the passphrase
+struct EncryptedKey {
+    public_key: PublicKey,
+    kdf: KdfParams,
+    #[serde(with = "serde_hex::bytes")]
+    salt: Vec<u8>,
+    #[serde(with = "serde_hex::bytes")]
+    nonce: Vec<u8>,
+    /// Secret bytes of the key, authenticated together with the public key
+    #[serde(with = "serde_hex::bytes")]
+    ciphertext: Vec<u8>,
+}
+
+/// Recommended by RFC 9106 for environments where 64 MiB of memory is affordable
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 4,
+        }
+    }
+}
+
+impl KdfParams {
+    fn derive_key(&self, passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(KeystoreError::Kdf)?;
+        let mut key = Zeroizing::new([0; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+            .map_err(KeystoreError::Kdf)?;
+        Ok(key)
+    }
+}
+
+impl Keystore {
+    /// Version of the file format
+    pub const VERSION: u32 = 1;
+    const SALT_SIZE: usize = 16;
+    const NONCE_SIZE: usize = 12;
+
+    /// Open the key store saved at `path`, which is created when the first key is imported
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Self {
+                    path,
+                    kdf: KdfParams::default(),
+                    keys: BTreeMap::new(),
+                })
+            }
+            Err(e) => return Err(KeystoreError::from(e).into()),
+        };
+        let file: KeystoreFile = serde_json::from_slice(&content).map_err(KeystoreError::from)?;
+        if file.version != Self::VERSION {
+            return Err(KeystoreError::UnsupportedVersion {
+                version: file.version,
+            }
+            .into());
+        }
+        Ok(Self {
+            path,
+            kdf: KdfParams::default(),
+            keys: file.keys,
+        })
+    }
+
+    /// Use `kdf` to encrypt the keys imported from now on
+    pub fn with_kdf(mut self, kdf: KdfParams) -> Self {
+        self.kdf = kdf;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Names and public keys of the stored keys, ordered by name
+    pub fn list(&self) -> impl Iterator<Item = (&str, &PublicKey)> {
+        self.keys
+            .iter()
+            .map(|(name, key)| (name.as_str(), &key.public_key))
+    }
+
+    /// Encrypt the private key with the passphrase and save it as `name`
+    pub fn import(&mut self, name: &str, private_key: &PrivateKey, passphrase: &str) -> Result<()> {
+        if self.keys.contains_key(name) {
+            return Err(KeystoreError::DuplicateKey {
+                name: name.to_owned(),
+            }
+            .into());
+        }
+        let mut salt = vec![0; Self::SALT_SIZE];
+        let mut nonce = vec![0; Self::NONCE_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        let public_key = private_key.to_public_key();
+        let key = self.kdf.derive_key(passphrase, &salt)?;
+        let secret = private_key.to_secret_bytes()?;
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &secret,
+                    aad: &public_key.encode(),
+                },
+            )
+            .expect("plaintext is within the size limit of ChaCha20-Poly1305");
+        self.keys.insert(
+            name.to_owned(),
+            EncryptedKey {
+                public_key,
+                kdf: self.kdf,
+                salt,
+                nonce,
+                ciphertext,
+            },
+        );
+        if let Err(e) = self.save() {
+            self.keys.remove(name);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Decrypt the private key saved as `name` with its passphrase
+    pub fn export(&self, name: &str, passphrase: &str) -> Result<PrivateKey> {
+        let encrypted = self.keys.get(name).ok_or_else(|| unknown_key(name))?;
+        let wrong_passphrase = || KeystoreError::WrongPassphrase {
+            name: name.to_owned(),
+        };
+        if encrypted.nonce.len() != Self::NONCE_SIZE {
+            return Err(wrong_passphrase().into());
+        }
+        let key = encrypted.kdf.derive_key(passphrase, &encrypted.salt)?;
+        let secret = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+            .decrypt(
+                Nonce::from_slice(&encrypted.nonce),
+                Payload {
+                    msg: &encrypted.ciphertext,
+                    aad: &encrypted.public_key.encode(),
+                },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| wrong_passphrase())?;
+        PrivateKey::from_secret_bytes(&secret)
+    }
+
+    /// Remove the key saved as `name` from the key store
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        let removed = self.keys.remove(name).ok_or_else(|| unknown_key(name))?;
+        if let Err(e) = self.save() {
+            self.keys.insert(name.to_owned(), removed);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Replace the file with the current keys, so that the file is never left half written
+    fn save(&self) -> Result<()> {
+        let file = KeystoreFile {
+            version: Self::VERSION,
+            keys: self.keys.clone(),
+        };
+        let content = serde_json::to_vec_pretty(&file).map_err(KeystoreError::from)?;
+        let temp_path = self.path.with_extension("tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // Only the owner can read the encrypted keys
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let write = || -> std::io::Result<()> {
+            let mut temp = options.open(&temp_path)?;
+            temp.write_all(&content)?;
+            temp.sync_all()?;
+            fs::rename(&temp_path, &self.path)
+        };
+        write().map_err(|e| KeystoreError::from(e).into())
+    }
+}
+
+fn unknown_key(name: &str) -> KeystoreError {
+    KeystoreError::UnknownKey {
+        name: name.to_owned(),
+    }
+}
+
+#[test]
+fn test_keystore() {
+    use super::{PrimitiveError, SignatureScheme};
+    let path = std::env::temp_dir().join(format!("tipchune-keystore-{}.json", std::process::id()));
+    let _ = fs::remove_file(&path);
+    // Cheap parameters to keep the test fast
+    let kdf = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+    let mut rng = rand::thread_rng();
+    let mining_key = PrivateKey::generate(SignatureScheme::Ed25519, &mut rng).unwrap();
+    let wallet_key = PrivateKey::Rsa(rsa::RsaPrivateKey::new(&mut rng, 512).unwrap());
+
+    let mut keystore = Keystore::open(&path).unwrap().with_kdf(kdf);
+    assert_eq!(0, keystore.list().count());
+    keystore
+        .import("wallet", &wallet_key, "wallet pass")
+        .unwrap();
+    keystore
+        .import("mining", &mining_key, "mining pass")
+        .unwrap();
+    assert!(matches!(
+        keystore.import("mining", &wallet_key, "other pass"),
+        Err(PrimitiveError::Keystore(KeystoreError::DuplicateKey { .. }))
+    ));
+
+    // The secret bytes never appear in the file
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(!content.contains(&hex::encode(&mining_key.to_secret_bytes().unwrap()[1..])));
+
+    let mut keystore = Keystore::open(&path).unwrap();
+    let names: Vec<_> = keystore.list().map(|(name, _)| name).collect();
+    assert_eq!(vec!["mining", "wallet"], names);
+    for (name, private_key, passphrase) in [
+        ("mining", &mining_key, "mining pass"),
+        ("wallet", &wallet_key, "wallet pass"),
+    ] {
+        let exported = keystore.export(name, passphrase).unwrap();
+        assert_eq!(
+            private_key.to_secret_bytes().unwrap(),
+            exported.to_secret_bytes().unwrap()
+        );
+        assert!(matches!(
+            keystore.export(name, "wrong pass"),
+            Err(PrimitiveError::Keystore(
+                KeystoreError::WrongPassphrase { .. }
+            ))
+        ));
+    }
+
+    keystore.delete("wallet").unwrap();
+    assert!(matches!(
+        keystore.export("wallet", "wallet pass"),
+        Err(PrimitiveError::Keystore(KeystoreError::UnknownKey { .. }))
+    ));
+    assert_eq!(1, Keystore::open(&path).unwrap().list().count());
+    fs::remove_file(&path).unwrap();
+}
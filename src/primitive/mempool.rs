@@ -0,0 +1,483 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    Amount, Encode, Hash, Hashable, SignatureCache, Transaction, TxOut, TxOutPtr, UtxoSet,
+};
+use super::{PrimitiveError, Result};
+
+#[derive(Debug, Clone)]
+/// Transaction waiting in the mempool to be included in a block
+pub struct MempoolEntry {
+    /// The pending transaction
+    transaction: Transaction,
+    /// Amount left to the miner, which is the input amount minus the output amount
+    fee: Amount,
+    /// Size of the encoded transaction in bytes
+    size: usize,
+    /// Order in which the transaction was accepted
+    sequence: u64,
+    /// Unconfirmed transactions whose outputs this transaction spends
+    parents: HashSet<Hash>,
+    /// Unconfirmed transactions spending the outputs of this transaction
+    children: HashSet<Hash>,
+}
+
+#[derive(Debug)]
+/// Transactions which are valid on top of the main chain but not included in any block yet
+pub struct Mempool {
+    /// Pending transactions keyed by their hash
+    entries: HashMap<Hash, MempoolEntry>,
+    /// Hash of the pending transaction spending each output
+    spent: HashMap<TxOutPtr, Hash>,
+    /// Total size of the pending transactions in bytes
+    total_size: usize,
+    /// Maximum total size of the pending transactions in bytes
+    max_size: usize,
+    /// Sequence number given to the next accepted transaction
+    next_sequence: u64,
+}
+
+impl MempoolEntry {
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    pub fn fee(&self) -> Amount {
+        self.fee
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn parents(&self) -> &HashSet<Hash> {
+        &self.parents
+    }
+
+    pub fn children(&self) -> &HashSet<Hash> {
+        &self.children
+    }
+
+    /// Whether this entry pays a lower fee per byte than `other`
+    fn pays_less_than(&self, other: &Self) -> bool {
+        let fee_times_size =
+            |entry: &Self, size: usize| entry.fee.as_base_units() as u128 * size as u128;
+        fee_times_size(self, other.size) < fee_times_size(other, self.size)
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX_SIZE)
+    }
+}
+
+impl Mempool {
+    pub const DEFAULT_MAX_SIZE: usize = 32 * 1024 * 1024;
+
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            spent: HashMap::new(),
+            total_size: 0,
+            max_size,
+            next_sequence: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total size of the pending transactions in bytes
+    pub fn size(&self) -> usize {
+        self.total_size
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&MempoolEntry> {
+        self.entries.get(hash)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&Hash, &MempoolEntry)> {
+        self.entries.iter()
+    }
+
+    /// Pick up to `limit` transactions paying the highest fee per byte to be included in a block.
+    /// A transaction is picked only after all of its pending parents, so the result is in dependency order.
+    pub fn select(&self, limit: usize) -> Vec<&MempoolEntry> {
+        let mut candidates: Vec<(&Hash, &MempoolEntry)> = self.entries.iter().collect();
+        candidates.sort_by(|(_, a), (_, b)| {
+            if a.pays_less_than(b) {
+                Ordering::Greater
+            } else if b.pays_less_than(a) {
+                Ordering::Less
+            } else {
+                a.sequence.cmp(&b.sequence)
+            }
+        });
+        let mut selected = Vec::new();
+        let mut selected_hashes = HashSet::new();
+        while selected.len() < limit {
+            let next = candidates.iter().position(|(_, entry)| {
+                entry
+                    .parents
+                    .iter()
+                    .all(|parent| selected_hashes.contains(parent))
+            });
+            match next {
+                Some(next) => {
+                    let (hash, entry) = candidates.remove(next);
+                    selected_hashes.insert(*hash);
+                    selected.push(entry);
+                }
+                None => break,
+            }
+        }
+        selected
+    }
+
+    /// Validate the transaction against `utxos`, the unspent outputs at the tip of the main chain, and keep it.
+    /// `height` is the height of the next block, in which outputs of base transactions must have matured.
+    /// The inputs may also spend outputs of pending transactions, which become the parents of the transaction.
+    /// Valid signatures are remembered in `signatures`, so that they are not verified again in a block.
+    /// If the pool gets full, the transactions paying the lowest fee per byte are evicted with their descendants.
+    pub fn insert(
+        &mut self,
+        transaction: Transaction,
+        utxos: &UtxoSet,
+        height: u128,
+        coinbase_maturity: u128,
+        signatures: &SignatureCache,
+    ) -> Result<Hash> {
+        let hash = transaction.hash();
+        let rejected = |reason| PrimitiveError::MempoolRejected {
+            transaction: hash,
+            reason,
+        };
+        if self.contains(&hash) {
+            return Err(rejected("transaction already exists in mempool"));
+        }
+        if transaction.inputs().is_empty() || transaction.coinbase_height() != 0 {
+            return Err(rejected("base transaction cannot enter mempool"));
+        }
+        let overflow = || PrimitiveError::AmountOverflow { transaction: hash };
+
+        let sighash = transaction.sighash();
+        let mut parents = HashSet::new();
+        let mut source_outputs = HashSet::new();
+        let mut input_amount = Amount::ZERO;
+        for (index, input) in transaction.inputs().iter().enumerate() {
+            let source_output = input.source_output();
+            // Spending an output twice in the transaction, or conflicting with another pending transaction
+            if !source_outputs.insert(source_output) || self.spent.contains_key(source_output) {
+                return Err(PrimitiveError::DoubleSpend {
+                    transaction: hash,
+                    output: source_output.clone(),
+                });
+            }
+            let source = self.find_source(
+                &hash,
+                source_output,
+                utxos,
+                height,
+                coinbase_maturity,
+                &mut parents,
+            )?;
+            if !input.is_signed_by_owner(source, &sighash, signatures) {
+                return Err(PrimitiveError::BadSignature {
+                    transaction: hash,
+                    input: index,
+                });
+            }
+            input_amount = input_amount
+                .checked_add(source.amount())
+                .ok_or_else(overflow)?;
+        }
+        let output_amount = Amount::checked_sum(transaction.outputs().iter().map(TxOut::amount))
+            .ok_or_else(overflow)?;
+        let fee = input_amount
+            .checked_sub(output_amount)
+            .ok_or(PrimitiveError::Unbalanced {
+                transaction: hash,
+                input: input_amount,
+                output: output_amount,
+            })?;
+        let size = transaction.encode().len();
+        if size > self.max_size {
+            return Err(rejected("transaction is larger than mempool"));
+        }
+
+        for input in transaction.inputs() {
+            self.spent.insert(input.source_output().clone(), hash);
+        }
+        for parent in &parents {
+            if let Some(parent) = self.entries.get_mut(parent) {
+                parent.children.insert(hash);
+            }
+        }
+        self.entries.insert(
+            hash,
+            MempoolEntry {
+                transaction,
+                fee,
+                size,
+                sequence: self.next_sequence,
+                parents,
+                children: HashSet::new(),
+            },
+        );
+        self.next_sequence += 1;
+        self.total_size += size;
+
+        self.evict();
+        if !self.contains(&hash) {
+            return Err(rejected(
+                "fee rate of transaction is too low to enter mempool",
+            ));
+        }
+        Ok(hash)
+    }
+
+    /// Output referred by an input of the transaction of `spender`,
+    /// either unspent in the main chain or created by a pending transaction
+    fn find_source<'a>(
+        &'a self,
+        spender: &Hash,
+        source_output: &TxOutPtr,
+        utxos: &'a UtxoSet,
+        height: u128,
+        coinbase_maturity: u128,
+        parents: &mut HashSet<Hash>,
+    ) -> Result<&'a TxOut> {
+        if let Some(entry) = utxos.get(source_output) {
+            if !entry.is_spendable_at(height, coinbase_maturity) {
+                return Err(PrimitiveError::ImmatureCoinbase {
+                    transaction: *spender,
+                    output: source_output.clone(),
+                });
+            }
+            return Ok(entry.output());
+        }
+        let parent_hash = source_output.transaction_hash();
+        let source = self
+            .entries
+            .get(parent_hash)
+            .and_then(|parent| parent.transaction.outputs().get(source_output.index()))
+            .ok_or_else(|| PrimitiveError::MissingInput {
+                transaction: *spender,
+                output: source_output.clone(),
+            })?;
+        parents.insert(*parent_hash);
+        Ok(source)
+    }
+
+    /// Drop the transactions paying the lowest fee per byte until the pool fits in `max_size`.
+    /// The newest transaction is dropped first among those paying the same rate.
+    fn evict(&mut self) {
+        while self.total_size > self.max_size {
+            let lowest = self
+                .entries
+                .iter()
+                .reduce(|lowest, entry| {
+                    let (_, lowest_entry) = lowest;
+                    let (_, candidate) = entry;
+                    if candidate.pays_less_than(lowest_entry)
+                        || (!lowest_entry.pays_less_than(candidate)
+                            && candidate.sequence > lowest_entry.sequence)
+                    {
+                        entry
+                    } else {
+                        lowest
+                    }
+                })
+                .map(|(hash, _)| *hash);
+            match lowest {
+                Some(lowest) => self.remove_with_descendants(&lowest),
+                None => break,
+            }
+        }
+    }
+
+    /// Remove the transactions included in a block connected to the main chain,
+    /// and the pending transactions which conflict with them together with their descendants
+    pub fn remove_for_block(&mut self, transactions: &[Transaction]) {
+        for transaction in transactions {
+            let hash = transaction.hash();
+            if let Some(entry) = self.remove(&hash) {
+                // The children now spend confirmed outputs
+                for child in &entry.children {
+                    if let Some(child) = self.entries.get_mut(child) {
+                        child.parents.remove(&hash);
+                    }
+                }
+            }
+            for input in transaction.inputs() {
+                if let Some(conflict) = self.spent.get(input.source_output()).copied() {
+                    self.remove_with_descendants(&conflict);
+                }
+            }
+        }
+    }
+
+    /// Remove every pending transaction, returning them in the order they were accepted
+    pub fn drain(&mut self) -> Vec<Transaction> {
+        let mut entries: Vec<MempoolEntry> = self.entries.drain().map(|(_, entry)| entry).collect();
+        entries.sort_by_key(|entry| entry.sequence);
+        self.spent.clear();
+        self.total_size = 0;
+        entries.into_iter().map(|entry| entry.transaction).collect()
+    }
+
+    /// Remove the transaction and every pending transaction depending on it
+    fn remove_with_descendants(&mut self, hash: &Hash) {
+        let mut stack = vec![*hash];
+        while let Some(hash) = stack.pop() {
+            if let Some(entry) = self.remove(&hash) {
+                stack.extend(entry.children);
+            }
+        }
+    }
+
+    /// Remove a single transaction, detaching it from its parents
+    fn remove(&mut self, hash: &Hash) -> Option<MempoolEntry> {
+        let entry = self.entries.remove(hash)?;
+        for input in entry.transaction.inputs() {
+            self.spent.remove(input.source_output());
+        }
+        for parent in &entry.parents {
+            if let Some(parent) = self.entries.get_mut(parent) {
+                parent.children.remove(hash);
+            }
+        }
+        self.total_size -= entry.size;
+        Some(entry)
+    }
+}
+
+#[test]
+fn test_mempool_validation_and_eviction() {
+    use super::{Address, PrivateKey, SignatureScheme, TxIn, UtxoView};
+    let private_key = PrivateKey::generate(SignatureScheme::Ed25519, &mut rand::thread_rng())
+        .expect("failed to create private_key");
+    let address = Address::from_public_key(&private_key.to_public_key()).unwrap();
+
+    let funding = Transaction::new(
+        Vec::new(),
+        vec![TxOut::new(address.clone(), Amount::new(100)); 3],
+    );
+    let empty = UtxoSet::new();
+    let mut view = UtxoView::new(&empty);
+    view.create(&funding, 1, true)
+        .expect("failed to create outputs");
+    let mut utxos = UtxoSet::new();
+    utxos.apply(&view.into_diff());
+
+    let spend = |source: TxOutPtr, amount| {
+        let mut transaction = Transaction::new(
+            vec![TxIn::new(private_key.to_public_key(), source)],
+            vec![TxOut::new(address.clone(), Amount::new(amount))],
+        );
+        transaction
+            .sign_inputs(std::slice::from_ref(&private_key))
+            .expect("failed to sign inputs");
+        transaction
+    };
+    let parent = spend(TxOutPtr::new(funding.hash(), 0), 90);
+    let child = spend(TxOutPtr::new(parent.hash(), 0), 85);
+    let conflict = spend(TxOutPtr::new(funding.hash(), 0), 80);
+
+    let signatures = SignatureCache::default();
+    let mut mempool = Mempool::default();
+    assert!(matches!(
+        mempool.insert(parent.clone(), &utxos, 2, 2, &signatures),
+        Err(PrimitiveError::ImmatureCoinbase { .. })
+    ));
+    let parent_hash = mempool
+        .insert(parent.clone(), &utxos, 2, 0, &signatures)
+        .expect("failed to insert a transaction");
+    let child_hash = mempool
+        .insert(child.clone(), &utxos, 2, 0, &signatures)
+        .expect("failed to insert a child transaction");
+    assert!(mempool
+        .get(&parent_hash)
+        .unwrap()
+        .children()
+        .contains(&child_hash));
+    assert!(mempool
+        .get(&child_hash)
+        .unwrap()
+        .parents()
+        .contains(&parent_hash));
+    assert_eq!(Amount::new(10), mempool.get(&parent_hash).unwrap().fee());
+    assert!(matches!(
+        mempool.insert(conflict.clone(), &utxos, 2, 0, &signatures),
+        Err(PrimitiveError::DoubleSpend { .. })
+    ));
+    assert!(matches!(
+        mempool.insert(
+            spend(TxOutPtr::new(funding.hash(), 1), 101),
+            &utxos,
+            2,
+            0,
+            &signatures
+        ),
+        Err(PrimitiveError::Unbalanced { .. })
+    ));
+    let unsigned = Transaction::new(
+        vec![TxIn::new(
+            private_key.to_public_key(),
+            TxOutPtr::new(funding.hash(), 1),
+        )],
+        vec![TxOut::new(address.clone(), Amount::new(90))],
+    );
+    assert!(matches!(
+        mempool.insert(unsigned, &utxos, 2, 0, &signatures),
+        Err(PrimitiveError::BadSignature { .. })
+    ));
+
+    // Confirming the parent keeps the child, and a conflicting block drops it
+    mempool.remove_for_block(std::slice::from_ref(&parent));
+    assert!(!mempool.contains(&parent_hash));
+    assert!(mempool.get(&child_hash).unwrap().parents().is_empty());
+    let mut mempool = Mempool::default();
+    mempool
+        .insert(parent.clone(), &utxos, 2, 0, &signatures)
+        .unwrap();
+    mempool
+        .insert(child.clone(), &utxos, 2, 0, &signatures)
+        .unwrap();
+    mempool.remove_for_block(&[conflict]);
+    assert!(mempool.is_empty());
+    assert_eq!(0, mempool.size());
+
+    // A full pool evicts the lowest fee rate first, with the descendants
+    let mut mempool = Mempool::new(2 * parent.encode().len());
+    mempool.insert(parent, &utxos, 2, 0, &signatures).unwrap();
+    mempool.insert(child, &utxos, 2, 0, &signatures).unwrap();
+    let rich = spend(TxOutPtr::new(funding.hash(), 1), 50);
+    let rich_hash = mempool
+        .insert(rich, &utxos, 2, 0, &signatures)
+        .expect("failed to evict");
+    assert!(mempool.contains(&parent_hash) && mempool.contains(&rich_hash));
+    assert!(!mempool.contains(&child_hash));
+    assert!(matches!(
+        mempool.insert(
+            spend(TxOutPtr::new(funding.hash(), 2), 99),
+            &utxos,
+            2,
+            0,
+            &signatures
+        ),
+        Err(PrimitiveError::MempoolRejected { .. })
+    ));
+    assert_eq!(2, mempool.len());
+}
@@ -0,0 +1,150 @@
+use sha2::{Digest, Sha256};
+
+use super::Hash;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Sibling of a node on the path from a leaf to the Merkle root
+pub enum MerkleStep {
+    /// The sibling is on the left of the node
+    Left(Hash),
+    /// The sibling is on the right of the node
+    Right(Hash),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Proof that a transaction is included in a block, checked against the Merkle root in the block header
+pub struct MerkleProof {
+    /// Siblings from the leaf up to the root
+    path: Vec<MerkleStep>,
+}
+
+/// Prefix of the hash of a leaf, distinguishing leaves from inner nodes
+const LEAF_PREFIX: u8 = 0x00;
+/// Prefix of the hash of an inner node
+const NODE_PREFIX: u8 = 0x01;
+
+/// Hash of a leaf, prefixed so that an inner node cannot be passed off as a leaf,
+/// e.g. `[H(a, b), c]` as the tree of `[a, b, c]`
+fn hash_leaf(leaf: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(leaf);
+    hasher.finalize().into()
+}
+
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Hashes of the leaves, which form the bottom level of the tree
+fn leaf_level(leaves: &[Hash]) -> Vec<Hash> {
+    leaves.iter().map(hash_leaf).collect()
+}
+
+/// Hashes of the next level of the tree.
+/// A node without a sibling is carried up as it is rather than paired with a copy of itself,
+/// so that repeating the last leaf changes the root.
+fn parent_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_pair(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Root of the Merkle tree whose leaves are `leaves`.
+/// The root of no leaves is the zero hash.
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return Hash::default();
+    }
+    let mut level = leaf_level(leaves);
+    while level.len() > 1 {
+        level = parent_level(&level);
+    }
+    level[0]
+}
+
+impl MerkleProof {
+    /// Build the proof for the leaf at `index`
+    pub fn new(leaves: &[Hash], index: usize) -> Option<Self> {
+        if index >= leaves.len() {
+            return None;
+        }
+        let mut path = Vec::new();
+        let mut level = leaf_level(leaves);
+        let mut index = index;
+        while level.len() > 1 {
+            let sibling = index ^ 1;
+            if sibling < level.len() {
+                path.push(if sibling < index {
+                    MerkleStep::Left(level[sibling])
+                } else {
+                    MerkleStep::Right(level[sibling])
+                });
+            }
+            level = parent_level(&level);
+            index /= 2;
+        }
+        Some(Self { path })
+    }
+
+    pub fn path(&self) -> &[MerkleStep] {
+        &self.path
+    }
+
+    /// Check that `leaf` is included in the tree of `root`
+    pub fn verify(&self, leaf: &Hash, root: &Hash) -> bool {
+        let computed = self
+            .path
+            .iter()
+            .fold(hash_leaf(leaf), |node, step| match step {
+                MerkleStep::Left(sibling) => hash_pair(sibling, &node),
+                MerkleStep::Right(sibling) => hash_pair(&node, sibling),
+            });
+        &computed == root
+    }
+}
+
+#[test]
+fn test_merkle_proof() {
+    for len in 1..=7_u8 {
+        let leaves: Vec<Hash> = (0..len).map(|i| Hash::from([i; 32])).collect();
+        let root = merkle_root(&leaves);
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = MerkleProof::new(&leaves, index).expect("failed to build a proof");
+            assert!(proof.verify(leaf, &root));
+            assert!(!proof.verify(&Hash::from([0xff; 32]), &root));
+        }
+        assert!(MerkleProof::new(&leaves, leaves.len()).is_none());
+    }
+    assert_eq!(
+        hash_leaf(&Hash::from([3; 32])),
+        merkle_root(&[Hash::from([3; 32])])
+    );
+    // An inner node cannot stand in for the leaves below it
+    let leaves = [
+        Hash::from([1; 32]),
+        Hash::from([2; 32]),
+        Hash::from([3; 32]),
+    ];
+    let inner = hash_pair(&hash_leaf(&leaves[0]), &hash_leaf(&leaves[1]));
+    assert_ne!(merkle_root(&leaves), merkle_root(&[inner, leaves[2]]));
+    let forged = MerkleProof::new(&[inner, leaves[2]], 0).expect("failed to build a proof");
+    assert!(!forged.verify(&inner, &merkle_root(&leaves)));
+    assert_ne!(
+        merkle_root(&[Hash::from([1; 32]), Hash::from([2; 32])]),
+        merkle_root(&[
+            Hash::from([1; 32]),
+            Hash::from([2; 32]),
+            Hash::from([2; 32])
+        ])
+    );
+}
@@ -0,0 +1,106 @@
+use sha2::Digest;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+
+use super::{Block, Hash, VerifyPow};
+
+#[derive(Debug, Clone, Default)]
+/// Token to stop mining from another thread, e.g. when a new tip arrives
+pub struct CancelToken(Arc<AtomicBool>);
+
+#[derive(Debug, Clone)]
+/// Miner searches for a nonce with which the block meets the difficulty of PoW
+pub struct Miner {
+    /// Number of threads trying nonces in parallel
+    threads: usize,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Miner {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+        }
+    }
+
+    /// Mine the block until its hash meets the target declared in it, returning the sealed block.
+    /// Returns `None` if `cancel` is cancelled before a nonce is found.
+    pub fn mine(&self, block: Block, cancel: &CancelToken) -> Option<Block> {
+        let target = block.target().ok()?;
+        let hasher = block.hasher_without_nonce();
+        let found = AtomicBool::new(false);
+        let nonce = thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads)
+                .map(|first_nonce| {
+                    let hasher = &hasher;
+                    let found = &found;
+                    let target = &target;
+                    scope.spawn(move || {
+                        // Each thread tries the nonces congruent to `first_nonce` modulo the number of threads
+                        let mut nonce = first_nonce as u128;
+                        while !found.load(Ordering::Relaxed) && !cancel.is_cancelled() {
+                            let mut hasher = hasher.clone();
+                            hasher.update(nonce.to_le_bytes());
+                            if Hash::from(hasher.finalize()).pow_verified(target) {
+                                found.store(true, Ordering::Relaxed);
+                                return Some(nonce);
+                            }
+                            nonce = nonce.checked_add(self.threads as u128)?;
+                        }
+                        None
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().expect("mining thread panicked"))
+                .next()
+        })?;
+        let mut block = block;
+        block.set_nonce(nonce);
+        Some(block)
+    }
+}
+
+#[test]
+fn test_mine_and_cancel() {
+    use super::{Address, Amount, Hash, Hashable, SignatureScheme, Target, Transaction, TxOut};
+    let target = Target::from_leading_zero_bits(12);
+    let block = Block::new(
+        vec![Transaction::new(
+            Vec::new(),
+            vec![TxOut::new(
+                Address::new(SignatureScheme::Ed25519, Hash::default()),
+                Amount::new(0),
+            )],
+        )],
+        Hash::default(),
+        target,
+    );
+    let miner = Miner::new(4);
+
+    let sealed = miner
+        .mine(block.clone(), &CancelToken::new())
+        .expect("failed to mine the block");
+    assert!(sealed.hash().pow_verified(&target));
+
+    let cancel = CancelToken::new();
+    cancel.cancel();
+    assert!(miner.mine(block, &cancel).is_none());
+}
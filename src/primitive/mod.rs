@@ -1,7 +1,33 @@
+mod amount;
 mod blockchain;
 mod crypto;
+mod encoding;
 mod error;
+mod hash;
+mod hd_key;
+mod keystore;
+mod mempool;
+mod merkle;
+mod miner;
+mod orphan;
+mod pow;
+mod signature_cache;
+mod utxo;
 
+pub mod serde_hex;
+
+pub use amount::*;
 pub use blockchain::*;
 pub use crypto::*;
+pub use encoding::*;
 pub use error::*;
+pub use hash::*;
+pub use hd_key::*;
+pub use keystore::*;
+pub use mempool::*;
+pub use merkle::*;
+pub use miner::*;
+pub use orphan::*;
+pub use pow::*;
+pub use signature_cache::*;
+pub use utxo::*;
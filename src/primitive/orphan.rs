@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::{Block, Hash, Hashable};
+
+#[derive(Debug)]
+/// Blocks received before their parent, waiting for the parent to be accepted
+pub struct OrphanPool {
+    /// Orphan blocks and the time they were received, keyed by their hash
+    blocks: HashMap<Hash, (Block, Instant)>,
+    /// Hashes of orphan blocks keyed by their missing parent
+    children: HashMap<Hash, Vec<Hash>>,
+    /// Maximum number of blocks kept in the pool
+    max_blocks: usize,
+    /// How long a block is kept in the pool
+    max_age: Duration,
+}
+
+impl Default for OrphanPool {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX_BLOCKS, Self::DEFAULT_MAX_AGE)
+    }
+}
+
+impl OrphanPool {
+    pub const DEFAULT_MAX_BLOCKS: usize = 100;
+    pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(20 * 60);
+
+    pub fn new(max_blocks: usize, max_age: Duration) -> Self {
+        Self {
+            blocks: HashMap::new(),
+            children: HashMap::new(),
+            max_blocks,
+            max_age,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    /// Keep the block until its parent is accepted.
+    /// Expired blocks are dropped, and the oldest block is evicted if the pool is full.
+    pub fn insert(&mut self, block: Block) {
+        let hash = block.hash();
+        if self.max_blocks == 0 || self.contains(&hash) {
+            return;
+        }
+        self.expire(Instant::now());
+        while self.blocks.len() >= self.max_blocks {
+            let oldest = self
+                .blocks
+                .iter()
+                .min_by_key(|(_, (_, received))| *received)
+                .map(|(hash, _)| *hash);
+            match oldest {
+                Some(oldest) => {
+                    self.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        self.children
+            .entry(block.parent_hash())
+            .or_default()
+            .push(hash);
+        self.blocks.insert(hash, (block, Instant::now()));
+    }
+
+    /// Remove the orphan blocks whose parent is the block of `parent_hash`
+    pub fn take_children(&mut self, parent_hash: &Hash) -> Vec<Block> {
+        self.children
+            .remove(parent_hash)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|hash| self.blocks.remove(hash))
+            .map(|(block, _)| block)
+            .collect()
+    }
+
+    /// Parents which are needed to connect the orphan blocks and should be requested from peers
+    pub fn missing_parents(&self) -> Vec<Hash> {
+        self.children
+            .keys()
+            .filter(|parent_hash| !self.contains(parent_hash))
+            .copied()
+            .collect()
+    }
+
+    /// Drop the blocks received more than `max_age` before `now`
+    pub fn expire(&mut self, now: Instant) {
+        let expired: Vec<Hash> = self
+            .blocks
+            .iter()
+            .filter(|(_, (_, received))| now.saturating_duration_since(*received) > self.max_age)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in &expired {
+            self.remove(hash);
+        }
+    }
+
+    fn remove(&mut self, hash: &Hash) -> Option<Block> {
+        let (block, _) = self.blocks.remove(hash)?;
+        let parent_hash = block.parent_hash();
+        if let Some(siblings) = self.children.get_mut(&parent_hash) {
+            siblings.retain(|sibling| sibling != hash);
+            if siblings.is_empty() {
+                self.children.remove(&parent_hash);
+            }
+        }
+        Some(block)
+    }
+}
+
+#[test]
+fn test_orphan_pool_bounds() {
+    use super::{Address, Amount, SignatureScheme, Target, Transaction, TxOut};
+    let block = |parent: u8| {
+        Block::new(
+            vec![Transaction::new(
+                Vec::new(),
+                vec![TxOut::new(
+                    Address::new(SignatureScheme::Ed25519, Hash::default()),
+                    Amount::new(0),
+                )],
+            )],
+            Hash::from([parent; 32]),
+            Target::MAX,
+        )
+    };
+
+    let mut pool = OrphanPool::new(2, Duration::from_secs(60));
+    pool.insert(block(1));
+    pool.insert(block(2));
+    pool.insert(block(3));
+    assert_eq!(2, pool.len());
+    let mut missing = pool.missing_parents();
+    missing.sort();
+    assert_eq!(vec![Hash::from([2; 32]), Hash::from([3; 32])], missing);
+
+    assert_eq!(1, pool.take_children(&Hash::from([2; 32])).len());
+    assert!(pool.take_children(&Hash::from([2; 32])).is_empty());
+    assert_eq!(1, pool.len());
+
+    pool.expire(Instant::now() + Duration::from_secs(61));
+    assert!(pool.is_empty());
+    assert!(pool.missing_parents().is_empty());
+}
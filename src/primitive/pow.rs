@@ -0,0 +1,155 @@
+use rsa::BigUint;
+
+use super::Hash;
+use super::{PrimitiveError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// 256 bit threshold which the hash of a block must not exceed.
+/// The bytes are in big-endian order, so the hash is also compared as a big-endian number.
+pub struct Target([u8; 32]);
+
+pub trait VerifyPow {
+    fn pow_verified(&self, target: &Target) -> bool;
+}
+
+impl VerifyPow for Hash {
+    fn pow_verified(&self, target: &Target) -> bool {
+        self.as_bytes() <= &target.0
+    }
+}
+
+impl Target {
+    /// The easiest target, which every hash meets
+    pub const MAX: Self = Self([0xff; 32]);
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Target which requires `zero_bits` preceding bits of the hash to be zero
+    pub fn from_leading_zero_bits(zero_bits: u32) -> Self {
+        let mut bytes = [0xff; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let byte_zero_bits = zero_bits.saturating_sub(i as u32 * 8).min(8);
+            *byte = 0xff_u8.checked_shr(byte_zero_bits).unwrap_or(0);
+        }
+        Self(bytes)
+    }
+
+    /// Expected number of hashes to find one meeting the target, which measures the work of a block
+    pub fn work(&self) -> BigUint {
+        (BigUint::from(1_u8) << 256) / (BigUint::from_bytes_be(&self.0) + 1_u8)
+    }
+
+    /// Scale the target by the ratio of the time actually taken to mine blocks to the expected time.
+    /// The ratio is clamped into [1/4, 4] so that the difficulty changes gradually, and the result
+    /// never exceeds `limit`.
+    pub fn retarget(&self, actual_timespan: u64, expected_timespan: u64, limit: &Target) -> Self {
+        let expected_timespan = expected_timespan.max(1);
+        let actual_timespan = actual_timespan.clamp(
+            (expected_timespan / 4).max(1),
+            expected_timespan.saturating_mul(4),
+        );
+        let target = BigUint::from_bytes_be(&self.0) * BigUint::from(actual_timespan)
+            / BigUint::from(expected_timespan);
+        let bytes = target.to_bytes_be();
+        if bytes.len() > 32 {
+            return *limit;
+        }
+        let mut retargeted = [0; 32];
+        retargeted[32 - bytes.len()..].copy_from_slice(&bytes);
+        Self(retargeted).min(*limit)
+    }
+
+    /// Decode the compact representation stored in blocks.
+    /// The highest byte is the length of the target in bytes and the lower 3 bytes are its most significant digits.
+    pub fn from_compact(bits: u32) -> Result<Self> {
+        let size = (bits >> 24) as i64;
+        let mantissa = bits & 0x007f_ffff;
+        if mantissa != 0 && bits & 0x0080_0000 != 0 {
+            return Err(PrimitiveError::InvalidTarget { bits });
+        }
+        let mut bytes = [0; 32];
+        for (i, digit) in mantissa.to_be_bytes()[1..].iter().enumerate() {
+            let index = 32 - size + i as i64;
+            if index < 0 {
+                if *digit != 0 {
+                    return Err(PrimitiveError::InvalidTarget { bits });
+                }
+            } else if index < 32 {
+                bytes[index as usize] = *digit;
+            }
+        }
+        Ok(Self(bytes))
+    }
+
+    /// Encode the target into the compact representation, truncating to 3 significant bytes
+    pub fn to_compact(&self) -> u32 {
+        let first = match self.0.iter().position(|byte| *byte != 0) {
+            Some(first) => first,
+            None => return 0,
+        };
+        let mut size = 32 - first as u32;
+        let mut digits = [0; 4];
+        for (i, digit) in digits[1..].iter_mut().enumerate() {
+            *digit = self.0.get(first + i).copied().unwrap_or(0);
+        }
+        // The highest bit of the mantissa is a sign, so shift the digits to keep it clear
+        if digits[1] & 0x80 != 0 {
+            digits = [0, 0, digits[1], digits[2]];
+            size += 1;
+        }
+        size << 24 | u32::from_be_bytes(digits)
+    }
+}
+
+#[test]
+fn test_target_retarget() {
+    let target = Target::from_leading_zero_bits(16);
+    let limit = Target::from_leading_zero_bits(15);
+    assert_eq!(target, target.retarget(600, 600, &limit));
+    assert_eq!(
+        Target::from_leading_zero_bits(17),
+        target.retarget(300, 600, &limit)
+    );
+    assert_eq!(
+        Target::from_leading_zero_bits(18),
+        target.retarget(1, 600, &limit)
+    );
+    assert_eq!(limit, target.retarget(600 * 1000, 600, &limit));
+}
+
+#[test]
+fn test_target_work() {
+    assert_eq!(BigUint::from(1_u8), Target::MAX.work());
+    assert_eq!(
+        BigUint::from(1_u32 << 16),
+        Target::from_leading_zero_bits(16).work()
+    );
+}
+
+#[test]
+fn test_target_compact() {
+    let target = Target::from_compact(0x1d00_ffff).expect("failed to decode compact target");
+    let mut expected = [0; 32];
+    expected[4] = 0xff;
+    expected[5] = 0xff;
+    assert_eq!(Target::from_bytes(expected), target);
+    assert_eq!(0x1d00_ffff, target.to_compact());
+
+    assert_eq!(0x2100_ffff, Target::MAX.to_compact());
+    assert_eq!(
+        0x0312_3456,
+        Target::from_compact(0x0312_3456).unwrap().to_compact()
+    );
+    assert_eq!(
+        0x0112_0000,
+        Target::from_compact(0x0112_3456).unwrap().to_compact()
+    );
+    assert!(Target::from_compact(0x0492_3456).is_err());
+    assert!(Target::from_compact(0x2201_0000).is_err());
+}
@@ -0,0 +1,18 @@
+//! Helpers to represent bytes as hex strings in human-readable formats like JSON.
+//! Use them with `#[serde(with = "...")]`.
+
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+/// Bytes as a hex string
+pub mod bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        hex::decode(encoded).map_err(D::Error::custom)
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Mutex, PoisonError};
+
+use super::{Encode, Hash, SignedMessage};
+
+#[derive(Debug)]
+/// Bounded set of signatures which have been verified successfully, shared by the mempool and block validation.
+/// The oldest signature is forgotten first once the cache is full.
+pub struct SignatureCache {
+    /// Verified signatures, locked so that the cache can be filled while verifying through a shared reference
+    inner: Mutex<Entries>,
+    /// Maximum number of signatures remembered
+    capacity: usize,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Keys of the verified signatures, oldest first
+    order: VecDeque<Hash>,
+    set: HashSet<Hash>,
+}
+
+impl Default for SignatureCache {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl SignatureCache {
+    /// Default number of signatures remembered, which takes a few megabytes
+    pub const DEFAULT_CAPACITY: usize = 100_000;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Entries::default()),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the signature has been verified successfully
+    pub fn contains(&self, signed: &SignedMessage) -> bool {
+        self.entries().set.contains(&Self::key(signed))
+    }
+
+    /// Remember signatures which have been verified successfully
+    pub fn insert(&self, signed: &[SignedMessage]) {
+        let mut entries = self.entries();
+        for signed in signed {
+            let key = Self::key(signed);
+            if !entries.set.insert(key) {
+                continue;
+            }
+            entries.order.push_back(key);
+            if entries.order.len() > self.capacity {
+                if let Some(oldest) = entries.order.pop_front() {
+                    entries.set.remove(&oldest);
+                }
+            }
+        }
+    }
+
+    /// Verify the signature unless it has been verified, remembering it if it is valid
+    pub fn verify(&self, signed: &SignedMessage) -> bool {
+        if self.contains(signed) {
+            return true;
+        }
+        let is_valid = signed
+            .public_key
+            .verify(&signed.message, signed.signature)
+            .is_ok();
+        if is_valid {
+            self.insert(std::slice::from_ref(signed));
+        }
+        is_valid
+    }
+
+    /// Key committing to the message, the public key and the signature
+    fn key(signed: &SignedMessage) -> Hash {
+        let mut buf = Vec::new();
+        signed.message.encode_to(&mut buf);
+        signed.public_key.encode_to(&mut buf);
+        signed.signature.to_vec().encode_to(&mut buf);
+        Hash::digest(buf)
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, Entries> {
+        // The entries are consistent even if a thread panicked while holding the lock
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[test]
+fn test_signature_cache() {
+    use super::{PrivateKey, SignatureScheme};
+    let private_key =
+        PrivateKey::generate(SignatureScheme::Ed25519, &mut rand::thread_rng()).unwrap();
+    let public_key = private_key.to_public_key();
+    let signatures: Vec<_> = (0..3)
+        .map(|i| private_key.sign(&Hash::from([i; 32])).unwrap())
+        .collect();
+    let signed: Vec<_> = (0..3)
+        .map(|i| SignedMessage {
+            public_key: &public_key,
+            message: Hash::from([i as u8; 32]),
+            signature: &signatures[i],
+        })
+        .collect();
+
+    let cache = SignatureCache::new(2);
+    let forged = SignedMessage {
+        message: Hash::from([9; 32]),
+        ..signed[0]
+    };
+    assert!(!cache.verify(&forged));
+    assert!(cache.is_empty());
+    assert!(cache.verify(&signed[0]));
+    assert!(cache.contains(&signed[0]));
+    assert!(!cache.contains(&forged));
+
+    // The oldest signature is forgotten once the cache is full
+    cache.insert(&signed[1..]);
+    assert_eq!(2, cache.len());
+    assert!(!cache.contains(&signed[0]));
+    assert!(cache.contains(&signed[1]) && cache.contains(&signed[2]));
+}
@@ -0,0 +1,291 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{Hash, Hashable};
+use super::{PrimitiveError, Result};
+use super::{Transaction, TxOut, TxOutPtr};
+
+#[derive(Debug, Clone)]
+/// Unspent output together with where it was created
+pub struct UtxoEntry {
+    /// The unspent output
+    output: TxOut,
+    /// Height of the block which created the output
+    height: u128,
+    /// Whether the output was created by a base transaction
+    is_coinbase: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Set of the transaction outputs which have not been spent yet
+pub struct UtxoSet {
+    /// Unspent outputs keyed by the pointer to them
+    outputs: HashMap<TxOutPtr, UtxoEntry>,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Changes made to the unspent outputs by a block
+pub struct UtxoDiff {
+    /// Outputs consumed by the block
+    spent: Vec<(TxOutPtr, UtxoEntry)>,
+    /// Outputs generated by the block
+    created: Vec<(TxOutPtr, UtxoEntry)>,
+}
+
+#[derive(Debug)]
+/// Unspent outputs seen from a transaction being verified on top of a `UtxoSet`
+pub struct UtxoView<'base> {
+    /// Unspent outputs before the transactions were applied
+    base: &'base UtxoSet,
+    /// Outputs added to or removed (`None`) from the base by the blocks of a fork, without copying the base
+    fork: HashMap<TxOutPtr, Option<UtxoEntry>>,
+    /// Changes made by the transactions applied to the view
+    diff: UtxoDiff,
+    /// Outputs spent by the transactions applied to the view
+    spent: HashSet<TxOutPtr>,
+    /// Outputs generated by the transactions applied to the view
+    created: HashMap<TxOutPtr, UtxoEntry>,
+}
+
+impl UtxoEntry {
+    pub fn output(&self) -> &TxOut {
+        &self.output
+    }
+
+    pub fn height(&self) -> u128 {
+        self.height
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.is_coinbase
+    }
+
+    /// Whether the output can be spent in the block at `height`.
+    /// Outputs of base transactions must be buried under `coinbase_maturity` blocks.
+    pub fn is_spendable_at(&self, height: u128, coinbase_maturity: u128) -> bool {
+        !self.is_coinbase || height.saturating_sub(self.height) >= coinbase_maturity
+    }
+}
+
+impl UtxoSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, output: &TxOutPtr) -> Option<&UtxoEntry> {
+        self.outputs.get(output)
+    }
+
+    pub fn contains(&self, output: &TxOutPtr) -> bool {
+        self.outputs.contains_key(output)
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = (&TxOutPtr, &UtxoEntry)> {
+        self.outputs.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    /// Redo the changes made by a block.
+    /// Outputs are created before spent since a block can spend outputs generated by itself.
+    pub fn apply(&mut self, diff: &UtxoDiff) {
+        for (output_ptr, entry) in &diff.created {
+            self.outputs.insert(output_ptr.clone(), entry.clone());
+        }
+        for (output_ptr, _) in &diff.spent {
+            self.outputs.remove(output_ptr);
+        }
+    }
+
+    /// Undo the changes made by a block
+    pub fn revert(&mut self, diff: &UtxoDiff) {
+        for (output_ptr, entry) in &diff.spent {
+            self.outputs.insert(output_ptr.clone(), entry.clone());
+        }
+        for (output_ptr, _) in &diff.created {
+            self.outputs.remove(output_ptr);
+        }
+    }
+}
+
+impl UtxoDiff {
+    pub fn spent(&self) -> &[(TxOutPtr, UtxoEntry)] {
+        &self.spent
+    }
+
+    pub fn created(&self) -> &[(TxOutPtr, UtxoEntry)] {
+        &self.created
+    }
+}
+
+impl<'base> UtxoView<'base> {
+    pub fn new(base: &'base UtxoSet) -> Self {
+        Self {
+            base,
+            fork: HashMap::new(),
+            diff: UtxoDiff::default(),
+            spent: HashSet::new(),
+            created: HashMap::new(),
+        }
+    }
+
+    /// Redo the changes made by a block on top of the base.
+    /// Blocks must be applied or reverted before any transaction is applied to the view.
+    pub fn apply(&mut self, diff: &UtxoDiff) {
+        for (output_ptr, entry) in &diff.created {
+            self.fork.insert(output_ptr.clone(), Some(entry.clone()));
+        }
+        for (output_ptr, _) in &diff.spent {
+            self.fork.insert(output_ptr.clone(), None);
+        }
+    }
+
+    /// Undo the changes made by a block on top of the base
+    pub fn revert(&mut self, diff: &UtxoDiff) {
+        for (output_ptr, entry) in &diff.spent {
+            self.fork.insert(output_ptr.clone(), Some(entry.clone()));
+        }
+        for (output_ptr, _) in &diff.created {
+            self.fork.insert(output_ptr.clone(), None);
+        }
+    }
+
+    /// Look up an output which is unspent in the view
+    pub fn get(&self, output_ptr: &TxOutPtr) -> Option<&UtxoEntry> {
+        if self.spent.contains(output_ptr) {
+            return None;
+        }
+        self.created
+            .get(output_ptr)
+            .or_else(|| self.get_before(output_ptr))
+    }
+
+    /// Look up an output which was unspent before the transactions were applied
+    fn get_before(&self, output_ptr: &TxOutPtr) -> Option<&UtxoEntry> {
+        match self.fork.get(output_ptr) {
+            Some(entry) => entry.as_ref(),
+            None => self.base.get(output_ptr),
+        }
+    }
+
+    /// Mark an output as spent by the transaction of `spender`, failing if it is missing or already spent
+    pub fn spend(&mut self, spender: &Hash, output_ptr: &TxOutPtr) -> Result<UtxoEntry> {
+        if self.spent.contains(output_ptr) {
+            return Err(PrimitiveError::DoubleSpend {
+                transaction: *spender,
+                output: output_ptr.clone(),
+            });
+        }
+        let entry = self
+            .get(output_ptr)
+            .cloned()
+            .ok_or_else(|| PrimitiveError::MissingInput {
+                transaction: *spender,
+                output: output_ptr.clone(),
+            })?;
+        self.spent.insert(output_ptr.clone());
+        self.diff.spent.push((output_ptr.clone(), entry.clone()));
+        Ok(entry)
+    }
+
+    /// Add the outputs of a transaction in the block at `height` as unspent.
+    /// `is_coinbase` tells whether it is the base transaction of the block, whose outputs must mature before spent.
+    pub fn create(
+        &mut self,
+        transaction: &Transaction,
+        height: u128,
+        is_coinbase: bool,
+    ) -> Result<()> {
+        let transaction_hash = transaction.hash();
+        for (index, output) in transaction.outputs().iter().enumerate() {
+            let output_ptr = TxOutPtr::new(transaction_hash, index);
+            if self.get_before(&output_ptr).is_some() || self.created.contains_key(&output_ptr) {
+                return Err(PrimitiveError::DuplicateTransaction {
+                    transaction: transaction_hash,
+                });
+            }
+            let entry = UtxoEntry {
+                output: output.clone(),
+                height,
+                is_coinbase,
+            };
+            self.created.insert(output_ptr.clone(), entry.clone());
+            self.diff.created.push((output_ptr, entry));
+        }
+        Ok(())
+    }
+
+    /// Changes made to the base set through the view
+    pub fn into_diff(self) -> UtxoDiff {
+        self.diff
+    }
+}
+
+#[test]
+fn test_utxo_view_rejects_double_spend() {
+    use super::{Address, Amount, SignatureScheme};
+    let transaction = Transaction::coinbase(
+        3,
+        TxOut::new(
+            Address::new(SignatureScheme::Ed25519, Hash::default()),
+            Amount::new(42),
+        ),
+    );
+    let output_ptr = TxOutPtr::new(transaction.hash(), 0);
+
+    let empty = UtxoSet::new();
+    let mut view = UtxoView::new(&empty);
+    view.create(&transaction, 3, true)
+        .expect("failed to create outputs");
+    assert!(matches!(
+        view.create(&transaction, 3, true),
+        Err(PrimitiveError::DuplicateTransaction { .. })
+    ));
+    let diff = view.into_diff();
+
+    let mut utxos = UtxoSet::new();
+    utxos.apply(&diff);
+    let entry = utxos.get(&output_ptr).unwrap();
+    assert!(!entry.is_spendable_at(4, 2));
+    assert!(entry.is_spendable_at(5, 2));
+    let spender = Hash::from([1; 32]);
+    let mut view = UtxoView::new(&utxos);
+    assert_eq!(
+        Amount::new(42),
+        view.spend(&spender, &output_ptr)
+            .map(|entry| entry.output().amount())
+            .unwrap()
+    );
+    assert!(matches!(
+        view.spend(&spender, &output_ptr),
+        Err(PrimitiveError::DoubleSpend { .. })
+    ));
+    assert!(matches!(
+        view.spend(&spender, &TxOutPtr::new(transaction.hash(), 1)),
+        Err(PrimitiveError::MissingInput { .. })
+    ));
+
+    let spend_diff = view.into_diff();
+    utxos.apply(&spend_diff);
+    assert!(utxos.is_empty());
+
+    // A view reverting the spend sees the output again without changing the set
+    let mut view = UtxoView::new(&utxos);
+    view.revert(&spend_diff);
+    assert!(view.get(&output_ptr).is_some());
+    assert!(matches!(
+        view.create(&transaction, 3, true),
+        Err(PrimitiveError::DuplicateTransaction { .. })
+    ));
+    view.revert(&diff);
+    assert!(view.get(&output_ptr).is_none());
+    assert!(utxos.is_empty());
+
+    utxos.revert(&spend_diff);
+    assert!(utxos.contains(&output_ptr));
+}
@@ -0,0 +1,38 @@
+use std::env;
+use std::io::{Error, ErrorKind};
+
+use crate::services::framing::DEFAULT_MAX_MESSAGE_SIZE;
+
+#[derive(Debug, Clone, Copy)]
+/// Settings of the node, read from environment variables
+pub struct Config {
+    /// Limit of the size of a WebSocket frame or a message reassembled from frames received from a peer,
+    /// set by `TIPCHUNED_MAX_MESSAGE_SIZE`
+    pub max_message_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+impl Config {
+    const MAX_MESSAGE_SIZE_VAR: &'static str = "TIPCHUNED_MAX_MESSAGE_SIZE";
+
+    /// Read the settings from the environment, using the default of each unset variable
+    pub fn from_env() -> std::io::Result<Self> {
+        let mut config = Self::default();
+        if let Ok(value) = env::var(Self::MAX_MESSAGE_SIZE_VAR) {
+            config.max_message_size = value.parse().map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid {}: {}", Self::MAX_MESSAGE_SIZE_VAR, e),
+                )
+            })?;
+        }
+        Ok(config)
+    }
+}
@@ -1,12 +1,25 @@
-use actix_web::{App, HttpServer};
+use actix::Actor;
+use actix_web::{web::Data, App, HttpServer};
 
+mod config;
 mod routes;
 mod services;
 
+use config::Config;
+use services::websocket::WsConnectionManager;
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(|| App::new().configure(routes::route))
-        .bind(("127.0.0.1", 8080))?
-        .run()
-        .await
+    let config = Config::from_env()?;
+    // Started once rather than by each worker, so that peers on every worker receive the relayed messages
+    let connection_manager = Data::new(WsConnectionManager::new().start());
+    HttpServer::new(move || {
+        App::new()
+            .app_data(Data::new(config))
+            .app_data(connection_manager.clone())
+            .configure(routes::route)
+    })
+    .bind(("127.0.0.1", 8080))?
+    .run()
+    .await
 }
@@ -1,4 +1,4 @@
-use actix::{Actor, Addr};
+use actix::Addr;
 use actix_web::{
     get,
     web::{Data, Payload, ServiceConfig},
@@ -6,6 +6,7 @@ use actix_web::{
 };
 use actix_web_actors::ws;
 
+use crate::config::Config;
 use crate::services::websocket::{WsConnection, WsConnectionManager};
 
 #[get("/")]
@@ -13,13 +14,137 @@ async fn start_connection(
     req: HttpRequest,
     stream: Payload,
     connection_manager: Data<Addr<WsConnectionManager>>,
+    config: Data<Config>,
 ) -> Result<HttpResponse, Error> {
-    let connection = WsConnection::new(connection_manager.as_ref().clone());
-    ws::start(connection, &req, stream)
+    let connection =
+        WsConnection::new(connection_manager.as_ref().clone(), config.max_message_size);
+    // Frames are limited to 64 KiB unless raised, which would drop peers relaying large blocks
+    ws::WsResponseBuilder::new(connection, &req, stream)
+        .frame_size(config.max_message_size)
+        .start()
 }
 
+/// Requires a `Data<Addr<WsConnectionManager>>` in the app data, shared by the apps of every worker
+/// so that messages are relayed to the peers connected to any worker
 pub fn route(cfg: &mut ServiceConfig) {
-    let connection_manager = WsConnectionManager::new().start();
-    cfg.app_data(connection_manager);
     cfg.service(start_connection);
 }
+
+#[actix_web::test]
+async fn test_relay_large_block() {
+    use actix::Actor;
+    use actix_codec::{Decoder, Encoder};
+    use actix_http::{
+        ws::{Codec, Frame, Message},
+        BoxedPayloadStream,
+    };
+    use actix_web::{
+        body::MessageBody,
+        dev::Service,
+        http::StatusCode,
+        rt::time::timeout,
+        test,
+        web::{Bytes, BytesMut},
+        App,
+    };
+    use futures_util::{stream, StreamExt};
+    use std::{future::poll_fn, pin::Pin, task::Poll, time::Duration};
+    use tipchune::primitive::{
+        Address, Amount, Block, Hash, SignatureScheme, Target, Transaction, TxOut,
+    };
+
+    use crate::services::{
+        framing::encode_frame,
+        message::{Inventory, Message as WsMessage},
+    };
+
+    let config = Config {
+        max_message_size: 1024 * 1024,
+    };
+    // Each worker builds its own app, sharing the connection manager
+    let connection_manager = Data::new(WsConnectionManager::new().start());
+    let app = || {
+        App::new()
+            .app_data(Data::new(config))
+            .app_data(connection_manager.clone())
+            .configure(route)
+    };
+    let receiving_worker = test::init_service(app()).await;
+    let sending_worker = test::init_service(app()).await;
+    let connect = |worker, frames: Vec<Bytes>| {
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header(("upgrade", "websocket"))
+            .insert_header(("connection", "upgrade"))
+            .insert_header(("sec-websocket-version", "13"))
+            .insert_header(("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .to_request();
+        // The peer keeps the connection open after sending the frames
+        let frames = stream::iter(frames.into_iter().map(Ok)).chain(stream::pending());
+        let (req, _) = req.replace_payload(actix_web::dev::Payload::Stream {
+            payload: Box::pin(frames) as BoxedPayloadStream,
+        });
+        Service::call(worker, req)
+    };
+
+    let mut client = Codec::new().client_mode();
+    let mut masked = |msg: &WsMessage| {
+        let mut buf = BytesMut::new();
+        client
+            .encode(Message::Binary(Bytes::from(encode_frame(msg))), &mut buf)
+            .unwrap();
+        buf.freeze()
+    };
+
+    // The receiving peer speaks binary frames, announced by an empty inventory
+    let inventory = masked(&WsMessage::Inventory(Inventory::default()));
+    let receiver = connect(&receiving_worker, vec![inventory]).await.unwrap();
+    assert_eq!(StatusCode::SWITCHING_PROTOCOLS, receiver.status());
+    let mut received = receiver.into_body();
+    // Start the receiving connection so that it joins before the block is relayed
+    assert!(poll_fn(|cx| Poll::Ready(Pin::new(&mut received).poll_next(cx).is_pending())).await);
+
+    let address = Address::new(SignatureScheme::Ed25519, Hash::from([1; 32]));
+    let outputs = (0..2000)
+        .map(|i| TxOut::new(address.clone(), Amount::new(i)))
+        .collect();
+    let block = Block::new(
+        vec![Transaction::new(Vec::new(), outputs)],
+        Hash::default(),
+        Target::MAX,
+    );
+    let block = WsMessage::Block(block);
+    let frame = encode_frame(&block);
+    assert!(frame.len() > 64 * 1024);
+    let mut sender = connect(&sending_worker, vec![masked(&block)])
+        .await
+        .unwrap()
+        .into_body();
+    actix_web::rt::spawn(async move {
+        while poll_fn(|cx| Pin::new(&mut sender).poll_next(cx))
+            .await
+            .is_some()
+        {}
+    });
+
+    let mut client = Codec::new().client_mode().max_size(config.max_message_size);
+    let mut buf = BytesMut::new();
+    let relayed = timeout(Duration::from_secs(10), async {
+        loop {
+            match client.decode(&mut buf).unwrap() {
+                Some(Frame::Binary(relayed)) => break relayed,
+                Some(_) => (),
+                None => {
+                    let chunk = poll_fn(|cx| Pin::new(&mut received).poll_next(cx))
+                        .await
+                        .expect("connection closed before the block was relayed")
+                        .unwrap();
+                    buf.extend_from_slice(&chunk);
+                }
+            }
+        }
+    })
+    .await
+    .expect("block was not relayed");
+    assert_eq!(frame, relayed.to_vec());
+}
@@ -0,0 +1,224 @@
+use actix_http::ws;
+use actix_web::web::Bytes;
+use sha2::{Digest, Sha256};
+use tipchune::primitive::{
+    Block, Decode, Decoder, Encode, Hash, PrimitiveError, Transaction, ENCODING_VERSION,
+};
+
+use crate::services::message::{ErrorMessage, Inventory, Message};
+
+/// Default limit of the size of a message reassembled from WebSocket frames
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Size of the tag, the payload length and the checksum preceding the payload
+const HEADER_SIZE: usize = 1 + 4 + 4;
+
+const TAG_BLOCK: u8 = 1;
+const TAG_TRANSACTION: u8 = 2;
+const TAG_INVENTORY: u8 = 3;
+const TAG_ERROR: u8 = 4;
+
+/// First 4 bytes of SHA-256 of the payload
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = Sha256::digest(payload);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Encode the message into a binary frame:
+/// message-type tag (1 byte), payload length (u32 LE), checksum (4 bytes) and the payload.
+/// Blocks and transactions are carried in the canonical binary encoding of the library.
+pub fn encode_frame(msg: &Message) -> Vec<u8> {
+    let (tag, payload) = match msg {
+        Message::Block(block) => (TAG_BLOCK, block.encode()),
+        Message::Transaction(transaction) => (TAG_TRANSACTION, transaction.encode()),
+        Message::Inventory(inventory) => {
+            let mut payload = vec![ENCODING_VERSION];
+            inventory.blocks.encode_to(&mut payload);
+            inventory.transactions.encode_to(&mut payload);
+            (TAG_INVENTORY, payload)
+        }
+        Message::Error(error) => (TAG_ERROR, error.message.as_bytes().to_vec()),
+    };
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.push(tag);
+    frame.extend_from_slice(
+        &u32::try_from(payload.len())
+            .expect("payload exceeds u32")
+            .to_le_bytes(),
+    );
+    frame.extend_from_slice(&checksum(&payload));
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+/// Decode a binary frame, rejecting frames which are truncated, corrupted or too large
+pub fn decode_frame(frame: &[u8], max_message_size: usize) -> Result<Message, String> {
+    if frame.len() > max_message_size {
+        return Err("message exceeds the maximum size".to_owned());
+    }
+    if frame.len() < HEADER_SIZE {
+        return Err("frame is shorter than its header".to_owned());
+    }
+    let (header, payload) = frame.split_at(HEADER_SIZE);
+    let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len != payload.len() {
+        return Err("payload length does not match with the header".to_owned());
+    }
+    if header[5..] != checksum(payload) {
+        return Err("checksum of the payload does not match".to_owned());
+    }
+    let malformed = |e| format!("malformed payload: {}", e);
+    match header[0] {
+        TAG_BLOCK => Block::decode(payload)
+            .map(Message::Block)
+            .map_err(malformed),
+        TAG_TRANSACTION => Transaction::decode(payload)
+            .map(Message::Transaction)
+            .map_err(malformed),
+        TAG_INVENTORY => decode_inventory(payload)
+            .map(Message::Inventory)
+            .map_err(malformed),
+        TAG_ERROR => String::from_utf8(payload.to_vec())
+            .map(|message| Message::Error(ErrorMessage { message }))
+            .map_err(|e| format!("malformed payload: {}", e)),
+        tag => Err(format!("unknown message type: {}", tag)),
+    }
+}
+
+fn decode_inventory(payload: &[u8]) -> tipchune::primitive::Result<Inventory> {
+    let mut decoder = Decoder::new(payload);
+    let version = u8::decode_from(&mut decoder)?;
+    if version != ENCODING_VERSION {
+        return Err(PrimitiveError::Encoding(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+    let blocks = Vec::<Hash>::decode_from(&mut decoder)?;
+    let transactions = Vec::<Hash>::decode_from(&mut decoder)?;
+    if !decoder.is_empty() {
+        return Err(PrimitiveError::Encoding(
+            "trailing bytes after encoded value".to_owned(),
+        ));
+    }
+    Ok(Inventory {
+        blocks,
+        transactions,
+    })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+/// Message reassembled from continuation frames
+pub enum Reassembled {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug)]
+/// Buffer reassembling a fragmented message from WebSocket continuation frames
+pub struct Reassembler {
+    /// Fragments received so far, and whether the message is text
+    buf: Option<(bool, Vec<u8>)>,
+    /// Limit of the size of the reassembled message
+    max_message_size: usize,
+}
+
+impl Reassembler {
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            buf: None,
+            max_message_size,
+        }
+    }
+
+    /// Add a fragment, returning the message once its last fragment arrives
+    pub fn push(&mut self, item: ws::Item) -> Result<Option<Reassembled>, String> {
+        match item {
+            ws::Item::FirstText(bytes) => self.start(true, bytes).map(|_| None),
+            ws::Item::FirstBinary(bytes) => self.start(false, bytes).map(|_| None),
+            ws::Item::Continue(bytes) => self.append(bytes).map(|_| None),
+            ws::Item::Last(bytes) => {
+                self.append(bytes)?;
+                let (is_text, buf) = self.buf.take().expect("fragments were appended");
+                if is_text {
+                    String::from_utf8(buf)
+                        .map(|text| Some(Reassembled::Text(text)))
+                        .map_err(|_| "fragmented text is not valid UTF-8".to_owned())
+                } else {
+                    Ok(Some(Reassembled::Binary(buf)))
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, is_text: bool, bytes: Bytes) -> Result<(), String> {
+        if self.buf.is_some() {
+            return Err("new fragmented message started before the last one ended".to_owned());
+        }
+        self.buf = Some((is_text, Vec::new()));
+        self.append(bytes)
+    }
+
+    fn append(&mut self, bytes: Bytes) -> Result<(), String> {
+        let (_, buf) = self
+            .buf
+            .as_mut()
+            .ok_or_else(|| "continuation frame without a first frame".to_owned())?;
+        if buf.len() + bytes.len() > self.max_message_size {
+            self.buf = None;
+            return Err("message exceeds the maximum size".to_owned());
+        }
+        buf.extend_from_slice(&bytes);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_binary_frame() {
+    let inventory = Message::Inventory(Inventory {
+        blocks: vec![Hash::from([1; 32])],
+        transactions: vec![Hash::from([2; 32]), Hash::from([3; 32])],
+    });
+    let frame = encode_frame(&inventory);
+    match decode_frame(&frame, DEFAULT_MAX_MESSAGE_SIZE) {
+        Ok(Message::Inventory(decoded)) => {
+            assert_eq!(vec![Hash::from([1; 32])], decoded.blocks);
+            assert_eq!(2, decoded.transactions.len());
+        }
+        other => panic!("unexpected message: {:?}", other),
+    }
+
+    let mut corrupted = frame.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(decode_frame(&corrupted, DEFAULT_MAX_MESSAGE_SIZE).is_err());
+    assert!(decode_frame(&frame[..frame.len() - 1], DEFAULT_MAX_MESSAGE_SIZE).is_err());
+    assert!(decode_frame(&frame, frame.len() - 1).is_err());
+}
+
+#[test]
+fn test_reassemble_fragments() {
+    let mut reassembler = Reassembler::new(8);
+    assert_eq!(
+        Ok(None),
+        reassembler.push(ws::Item::FirstBinary(Bytes::from_static(b"abc")))
+    );
+    assert_eq!(
+        Ok(None),
+        reassembler.push(ws::Item::Continue(Bytes::from_static(b"de")))
+    );
+    assert_eq!(
+        Ok(Some(Reassembled::Binary(b"abcdef".to_vec()))),
+        reassembler.push(ws::Item::Last(Bytes::from_static(b"f")))
+    );
+
+    assert!(reassembler
+        .push(ws::Item::Continue(Bytes::from_static(b"x")))
+        .is_err());
+    assert_eq!(
+        Ok(None),
+        reassembler.push(ws::Item::FirstText(Bytes::from_static(b"12345")))
+    );
+    assert!(reassembler
+        .push(ws::Item::Last(Bytes::from_static(b"6789")))
+        .is_err());
+}
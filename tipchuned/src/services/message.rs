@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+use tipchune::primitive::{Block, Hash, Transaction};
+
+/// Version of the JSON message schema
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+/// JSON message exchanged over WebSocket, e.g.
+/// `{"version": 1, "type": "inventory", "payload": {"blocks": ["<hex>"], "transactions": []}}`
+pub struct Envelope {
+    /// Version of the schema the message follows
+    pub version: u32,
+    #[serde(flatten)]
+    pub message: Message,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+/// Content of the message
+pub enum Message {
+    /// Block to be verified and added to the blockchain
+    Block(Block),
+    /// Transaction to be included in a block
+    Transaction(Transaction),
+    /// Announcement of blocks and transactions the sender has
+    Inventory(Inventory),
+    /// Notification that the previous message was rejected
+    Error(ErrorMessage),
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+/// Hashes of blocks and transactions
+pub struct Inventory {
+    pub blocks: Vec<Hash>,
+    pub transactions: Vec<Hash>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// Reason why a message was rejected
+pub struct ErrorMessage {
+    pub message: String,
+}
+
+impl Envelope {
+    pub fn new(message: Message) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            message,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Message::Error(ErrorMessage {
+            message: message.into(),
+        }))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize a message")
+    }
+
+    /// Parse a message, rejecting the versions of the schema this node does not understand
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let envelope: Self =
+            serde_json::from_str(json).map_err(|e| format!("malformed message: {}", e))?;
+        if envelope.version != PROTOCOL_VERSION {
+            return Err(format!(
+                "unsupported protocol version: {}",
+                envelope.version
+            ));
+        }
+        Ok(envelope)
+    }
+}
+
+#[test]
+fn test_message_json() {
+    let inventory = Envelope::new(Message::Inventory(Inventory {
+        blocks: vec![Hash::from([0xab; 32])],
+        transactions: Vec::new(),
+    }));
+    let json = inventory.to_json();
+    assert_eq!(
+        format!(
+            r#"{{"version":1,"type":"inventory","payload":{{"blocks":["{}"],"transactions":[]}}}}"#,
+            "ab".repeat(32)
+        ),
+        json
+    );
+    match Envelope::from_json(&json).map(|envelope| envelope.message) {
+        Ok(Message::Inventory(inventory)) => {
+            assert_eq!(vec![Hash::from([0xab; 32])], inventory.blocks)
+        }
+        other => panic!("unexpected message: {:?}", other),
+    }
+
+    assert!(
+        Envelope::from_json(r#"{"version":2,"type":"error","payload":{"message":""}}"#).is_err()
+    );
+    assert!(Envelope::from_json(r#"{"version":1,"type":"unknown","payload":{}}"#).is_err());
+}
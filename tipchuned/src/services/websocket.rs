@@ -3,18 +3,55 @@ use actix::{
     StreamHandler,
 };
 use actix_web_actors::ws;
-use std::{sync::Arc, time::Duration};
-use tipchune::primitive::{Block, Transaction};
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+use tipchune::primitive::{Block, Hash, Hashable, Transaction};
+
+use crate::services::framing::{decode_frame, encode_frame, Reassembled, Reassembler};
+use crate::services::message::{Envelope, ErrorMessage, Message as WsMessage};
 
 pub struct WsConnectionManager {
     connections: Vec<Addr<WsConnection>>,
+    /// Hashes of blocks and transactions already relayed, oldest first
+    relayed: VecDeque<Hash>,
+    relayed_set: HashSet<Hash>,
 }
 
 impl WsConnectionManager {
+    /// Number of relayed hashes remembered to avoid relaying the same item back and forth
+    const MAX_RELAYED: usize = 10_000;
+
     pub fn new() -> Self {
         Self {
             connections: Vec::new(),
+            relayed: VecDeque::new(),
+            relayed_set: HashSet::new(),
+        }
+    }
+
+    /// Remember the hash, returning false if it has already been relayed
+    fn mark_relayed(&mut self, hash: Hash) -> bool {
+        if !self.relayed_set.insert(hash) {
+            return false;
+        }
+        self.relayed.push_back(hash);
+        if self.relayed.len() > Self::MAX_RELAYED {
+            if let Some(oldest) = self.relayed.pop_front() {
+                self.relayed_set.remove(&oldest);
+            }
         }
+        true
+    }
+
+    fn peers_except(
+        &self,
+        origin: &Addr<WsConnection>,
+    ) -> impl Iterator<Item = &Addr<WsConnection>> {
+        let origin = origin.clone();
+        self.connections.iter().filter(move |conn| **conn != origin)
     }
 }
 
@@ -53,13 +90,22 @@ impl Handler<Leave> for WsConnectionManager {
 pub struct WsConnection {
     last_hb: Instant,
     manager: Addr<WsConnectionManager>,
+    /// Whether the peer speaks binary frames, in which messages are also sent to it
+    binary: bool,
+    /// Fragments of a message split into continuation frames
+    reassembler: Reassembler,
+    /// Limit of the size of a message received from the peer
+    max_message_size: usize,
 }
 
 impl WsConnection {
-    pub fn new(manager: Addr<WsConnectionManager>) -> Self {
+    pub fn new(manager: Addr<WsConnectionManager>, max_message_size: usize) -> Self {
         Self {
             last_hb: Instant::now(),
             manager,
+            binary: false,
+            reassembler: Reassembler::new(max_message_size),
+            max_message_size,
         }
     }
 
@@ -81,6 +127,68 @@ impl WsConnection {
     }
 }
 
+impl WsConnection {
+    /// Send the message in the format the peer speaks
+    fn send(&self, msg: WsMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.binary {
+            ctx.binary(encode_frame(&msg));
+        } else {
+            ctx.text(Envelope::new(msg).to_json());
+        }
+    }
+
+    fn handle_text(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        if text.len() > self.max_message_size {
+            self.close_oversized(ctx);
+            return;
+        }
+        match Envelope::from_json(text) {
+            Ok(envelope) => self.handle_message(envelope.message, ctx),
+            Err(e) => ctx.text(Envelope::error(e).to_json()),
+        }
+    }
+
+    fn handle_binary(&mut self, frame: &[u8], ctx: &mut ws::WebsocketContext<Self>) {
+        if frame.len() > self.max_message_size {
+            self.close_oversized(ctx);
+            return;
+        }
+        self.binary = true;
+        match decode_frame(frame, self.max_message_size) {
+            Ok(msg) => self.handle_message(msg, ctx),
+            Err(e) => ctx.binary(encode_frame(&WsMessage::Error(ErrorMessage { message: e }))),
+        }
+    }
+
+    fn close_oversized(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        self.close(ws::CloseCode::Size, "message exceeds the maximum size", ctx);
+    }
+
+    fn close(&mut self, code: ws::CloseCode, reason: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.close(Some(ws::CloseReason {
+            code,
+            description: Some(reason.to_owned()),
+        }));
+        ctx.stop();
+    }
+
+    fn handle_message(&mut self, msg: WsMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        match msg {
+            WsMessage::Block(block) => self.manager.do_send(RelayBlock {
+                block: Arc::new(block),
+                origin: ctx.address(),
+            }),
+            WsMessage::Transaction(transaction) => self.manager.do_send(RelayTransaction {
+                transaction: Arc::new(transaction),
+                origin: ctx.address(),
+            }),
+            // TODO: Request the announced items once the node stores blocks and transactions
+            WsMessage::Inventory(_) => (),
+            WsMessage::Error(_) => (),
+        }
+    }
+}
+
 impl Actor for WsConnection {
     type Context = ws::WebsocketContext<Self>;
 
@@ -99,6 +207,50 @@ impl Actor for WsConnection {
     }
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RelayBlock {
+    block: Arc<Block>,
+    origin: Addr<WsConnection>,
+}
+
+impl Handler<RelayBlock> for WsConnectionManager {
+    type Result = <RelayBlock as Message>::Result;
+
+    fn handle(&mut self, msg: RelayBlock, _ctx: &mut Self::Context) -> Self::Result {
+        if !self.mark_relayed(msg.block.hash()) {
+            return;
+        }
+        for conn in self.peers_except(&msg.origin) {
+            conn.do_send(ForwardBlock {
+                block: msg.block.clone(),
+            });
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RelayTransaction {
+    transaction: Arc<Transaction>,
+    origin: Addr<WsConnection>,
+}
+
+impl Handler<RelayTransaction> for WsConnectionManager {
+    type Result = <RelayTransaction as Message>::Result;
+
+    fn handle(&mut self, msg: RelayTransaction, _ctx: &mut Self::Context) -> Self::Result {
+        if !self.mark_relayed(msg.transaction.hash()) {
+            return;
+        }
+        for conn in self.peers_except(&msg.origin) {
+            conn.do_send(ForwardTransaction {
+                transaction: msg.transaction.clone(),
+            });
+        }
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct ForwardBlock {
@@ -108,9 +260,9 @@ pub struct ForwardBlock {
 impl Handler<ForwardBlock> for WsConnection {
     type Result = <ForwardBlock as Message>::Result;
 
-    fn handle(&mut self, _msg: ForwardBlock, ctx: &mut Self::Context) -> Self::Result {
-        //TODO: Define schema and send message over Websocket
-        ctx.text("forward block");
+    fn handle(&mut self, msg: ForwardBlock, ctx: &mut Self::Context) -> Self::Result {
+        let block = Arc::try_unwrap(msg.block).unwrap_or_else(|block| (*block).clone());
+        self.send(WsMessage::Block(block), ctx);
     }
 }
 
@@ -123,9 +275,10 @@ pub struct ForwardTransaction {
 impl Handler<ForwardTransaction> for WsConnection {
     type Result = <ForwardTransaction as Message>::Result;
 
-    fn handle(&mut self, _msg: ForwardTransaction, ctx: &mut Self::Context) -> Self::Result {
-        //TODO: Define schema and send message over Websocket
-        ctx.text("forward transaction");
+    fn handle(&mut self, msg: ForwardTransaction, ctx: &mut Self::Context) -> Self::Result {
+        let transaction =
+            Arc::try_unwrap(msg.transaction).unwrap_or_else(|transaction| (*transaction).clone());
+        self.send(WsMessage::Transaction(transaction), ctx);
     }
 }
 
@@ -147,17 +300,18 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsConnection {
             ws::Message::Pong(_) => {
                 self.last_hb = Instant::now();
             }
-            ws::Message::Text(_text) => {
-                //TODO: Define schema and parse message
-            }
-            ws::Message::Binary(_) => (),
+            ws::Message::Text(text) => self.handle_text(&text, ctx),
+            ws::Message::Binary(frame) => self.handle_binary(&frame, ctx),
             ws::Message::Close(reason) => {
                 ctx.close(reason);
                 ctx.stop();
             }
-            ws::Message::Continuation(_) => {
-                ctx.stop();
-            }
+            ws::Message::Continuation(item) => match self.reassembler.push(item) {
+                Ok(Some(Reassembled::Text(text))) => self.handle_text(&text, ctx),
+                Ok(Some(Reassembled::Binary(frame))) => self.handle_binary(&frame, ctx),
+                Ok(None) => (),
+                Err(e) => self.close(ws::CloseCode::Protocol, &e, ctx),
+            },
             ws::Message::Nop => (),
         }
     }